@@ -0,0 +1,59 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Exposes build information to the exporter as compile-time environment variables. Each variable
+/// is left unset if the information is unavailable, e.g., when building outside a git checkout.
+fn main() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    // HEAD usually refers to a branch, whose commit changes without HEAD itself changing. A missing
+    // path would rerun the script on every build, so only existing paths are watched.
+    let head_ref = fs::read_to_string(".git/HEAD")
+        .ok()
+        .and_then(|head| Some(format!(".git/{}", head.strip_prefix("ref: ")?.trim())));
+    for path in head_ref
+        .iter()
+        .map(String::as_str)
+        .chain([".git/refs/heads", ".git/packed-refs"].iter().copied())
+    {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+    println!("cargo:rerun-if-changed=Cargo.lock");
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    if let Some(version) = command_output(&rustc, &["--version"]) {
+        println!("cargo:rustc-env=SOLANA_EXPORTER_RUSTC={}", version);
+    }
+    if let Some(commit) = command_output("git", &["rev-parse", "--short", "HEAD"]) {
+        println!("cargo:rustc-env=SOLANA_EXPORTER_GIT_COMMIT={}", commit);
+    }
+    if let Some(version) = locked_version("solana-client") {
+        println!("cargo:rustc-env=SOLANA_EXPORTER_SOLANA_CLIENT={}", version);
+    }
+}
+
+/// Returns the trimmed standard output of `program` if it exits successfully.
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    Some(stdout.trim().to_string()).filter(|s| !s.is_empty())
+}
+
+/// Returns the version of `package` in `Cargo.lock`.
+fn locked_version(package: &str) -> Option<String> {
+    let lock = fs::read_to_string("Cargo.lock").ok()?;
+    let name = format!("name = \"{}\"", package);
+    let mut lines = lock.lines().skip_while(|line| *line != name);
+    lines.next()?;
+    lines
+        .next()?
+        .strip_prefix("version = \"")?
+        .strip_suffix('"')
+        .map(str::to_string)
+}
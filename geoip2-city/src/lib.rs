@@ -13,10 +13,10 @@ pub struct CityApiResponse {
     pub country: Option<Country>,
     pub location: Option<Location>,
     pub postal: Option<Postal>,
-    pub registered_country: Country,
+    pub registered_country: Option<Country>,
     pub represented_country: Option<RepresentedCountry>,
     pub subdivisions: Option<Vec<Subdivisions>>,
-    pub traits: Traits,
+    pub traits: Option<Traits>,
 }
 
 #[derive(Clone, Debug)]
@@ -1,7 +1,16 @@
 use crate::geolocation::api::MaxMindAPIKey;
+use crate::webhook::WebhookConfig;
+use log::warn;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use solana_client::rpc_response::RpcVoteAccountStatus;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::SystemTime;
 
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct Whitelist(pub HashSet<String>);
@@ -12,18 +21,511 @@ impl Whitelist {
     }
 }
 
+/// A newline-delimited file of vote account pubkeys that extends the inline whitelist and can be
+/// edited while the exporter is running. Blank lines and lines starting with `#` are ignored.
+pub struct WhitelistFile {
+    path: PathBuf,
+    /// Modification time of the file when it was last read.
+    mtime: Option<SystemTime>,
+    pubkeys: HashSet<String>,
+}
+
+impl WhitelistFile {
+    /// Makes a new whitelist file that is read on the first call to `reload`.
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            mtime: None,
+            pubkeys: HashSet::new(),
+        }
+    }
+
+    /// Re-reads the file if it was modified since it was last read. Returns `true` iff the file
+    /// was re-read. Invalid pubkeys are skipped with a warning.
+    pub fn reload(&mut self) -> anyhow::Result<bool> {
+        let mtime = fs::metadata(&self.path)?.modified()?;
+        if self.mtime == Some(mtime) {
+            return Ok(false);
+        }
+
+        let mut pubkeys = HashSet::new();
+        for line in fs::read_to_string(&self.path)?.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if Pubkey::from_str(line).is_ok() {
+                pubkeys.insert(line.to_string());
+            } else {
+                warn!("Ignoring invalid pubkey {} in {:?}", line, self.path);
+            }
+        }
+        self.pubkeys = pubkeys;
+        self.mtime = Some(mtime);
+        Ok(true)
+    }
+
+    /// Returns the union of `inline` and the pubkeys in the file.
+    pub fn union(&self, inline: &Whitelist) -> Whitelist {
+        Whitelist(inline.0.union(&self.pubkeys).cloned().collect())
+    }
+}
+
 pub const CONFIG_FILE_NAME: &str = "config.toml";
 
+/// Commitment level of RPC queries.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Commitment {
+    Processed,
+    Confirmed,
+    Finalized,
+}
+
+impl Default for Commitment {
+    fn default() -> Self {
+        Commitment::Finalized
+    }
+}
+
+impl From<Commitment> for CommitmentConfig {
+    fn from(commitment: Commitment) -> Self {
+        match commitment {
+            Commitment::Processed => CommitmentConfig::processed(),
+            Commitment::Confirmed => CommitmentConfig::confirmed(),
+            Commitment::Finalized => CommitmentConfig::finalized(),
+        }
+    }
+}
+
+/// Number of times a failed RPC call is retried by default.
+pub const DEFAULT_RPC_MAX_RETRIES: u32 = 5;
+
+fn default_rpc_max_retries() -> u32 {
+    DEFAULT_RPC_MAX_RETRIES
+}
+
+/// Default timeout of a single RPC request in seconds, the same as that of the Solana RPC client.
+pub const DEFAULT_RPC_TIMEOUT_SECONDS: u64 = 30;
+
+fn default_rpc_timeout_seconds() -> u64 {
+    DEFAULT_RPC_TIMEOUT_SECONDS
+}
+
+/// Default maximum number of accounts fetched in a single RPC call, which is the limit of public
+/// RPC nodes.
+pub const DEFAULT_ACCOUNT_FETCH_CHUNK_SIZE: usize = 100;
+
+fn default_account_fetch_chunk_size() -> usize {
+    DEFAULT_ACCOUNT_FETCH_CHUNK_SIZE
+}
+
+/// Hosts of the public RPC nodes, which do not accept more accounts in a single RPC call than
+/// `DEFAULT_ACCOUNT_FETCH_CHUNK_SIZE`.
+const PUBLIC_RPC_HOSTS: [&str; 3] = [
+    "api.mainnet-beta.solana.com",
+    "api.testnet.solana.com",
+    "api.devnet.solana.com",
+];
+
+/// Default interval between iterations of the main loop in seconds.
+pub const DEFAULT_SCRAPE_INTERVAL_SECONDS: u64 = 1;
+
+fn default_scrape_interval_seconds() -> u64 {
+    DEFAULT_SCRAPE_INTERVAL_SECONDS
+}
+
+/// Default maximum age of the last successful loop iteration for the exporter to be healthy.
+pub const DEFAULT_HEALTH_MAX_AGE_SECONDS: u64 = 60;
+
+fn default_health_max_age_seconds() -> u64 {
+    DEFAULT_HEALTH_MAX_AGE_SECONDS
+}
+
+/// Default number of epochs over which the average APY is calculated, inclusive of the current
+/// epoch.
+pub const DEFAULT_APY_LOOKBACK_EPOCHS: u64 = 5;
+
+fn default_apy_lookback_epochs() -> u64 {
+    DEFAULT_APY_LOOKBACK_EPOCHS
+}
+
+/// Default maximum number of concurrent requests to the MaxMind API.
+pub const DEFAULT_MAXMIND_MAX_CONCURRENT: usize = 10;
+
+fn default_maxmind_max_concurrent() -> usize {
+    DEFAULT_MAXMIND_MAX_CONCURRENT
+}
+
+fn default_enable_geolocation() -> bool {
+    true
+}
+
+/// Default timeout of MaxMind API requests in seconds.
+pub const DEFAULT_MAXMIND_TIMEOUT_SECONDS: u64 = 10;
+
+fn default_maxmind_timeout_seconds() -> u64 {
+    DEFAULT_MAXMIND_TIMEOUT_SECONDS
+}
+
+/// Number of days after which cached geolocation data is refreshed by default.
+pub const DEFAULT_GEO_CACHE_TTL_DAYS: i64 = 7;
+
+/// The time-to-live of cached geolocation data, in days.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum GeoCacheTtl {
+    /// The same TTL for every validator.
+    Uniform(i64),
+    /// TTLs that depend on the activated stake of the validator.
+    Tiered(Vec<GeoCacheTtlTier>),
+}
+
+/// A TTL that applies to validators with at least `min_stake` lamports of activated stake.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GeoCacheTtlTier {
+    pub min_stake: u64,
+    pub days: i64,
+}
+
+impl GeoCacheTtl {
+    /// Returns the TTL in days for a validator with `activated_stake`. With tiered TTLs, the tier
+    /// with the highest `min_stake` not exceeding `activated_stake` is used, falling back to
+    /// `DEFAULT_GEO_CACHE_TTL_DAYS` if there is no such tier.
+    pub fn days(&self, activated_stake: u64) -> i64 {
+        match self {
+            GeoCacheTtl::Uniform(days) => *days,
+            GeoCacheTtl::Tiered(tiers) => tiers
+                .iter()
+                .filter(|tier| tier.min_stake <= activated_stake)
+                .max_by_key(|tier| tier.min_stake)
+                .map(|tier| tier.days)
+                .unwrap_or(DEFAULT_GEO_CACHE_TTL_DAYS),
+        }
+    }
+}
+
+impl Default for GeoCacheTtl {
+    fn default() -> Self {
+        GeoCacheTtl::Uniform(DEFAULT_GEO_CACHE_TTL_DAYS)
+    }
+}
+
+/// A mapping of ASNs and ISO-3166 country codes to region names.
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct RegionMapping {
+    /// Regions by autonomous system number. These take precedence over countries.
+    #[serde(default)]
+    pub asns: HashMap<String, String>,
+    /// Regions by country code.
+    #[serde(default)]
+    pub countries: HashMap<String, String>,
+}
+
+impl RegionMapping {
+    /// Returns the region of an ASN or country code, if there is one.
+    pub fn region(&self, asn: Option<u32>, country_code: Option<&str>) -> Option<&str> {
+        asn.and_then(|asn| self.asns.get(&asn.to_string()))
+            .or_else(|| country_code.and_then(|code| self.countries.get(code)))
+            .map(String::as_str)
+    }
+}
+
+/// A filter of validators by their rank in activated stake.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RankFilter {
+    /// Only the given number of validators with the most stake.
+    Top(usize),
+    /// Only validators outside of the given number of validators with the most stake.
+    OutsideTop(usize),
+}
+
+impl RankFilter {
+    /// Returns the vote pubkeys of all validators in `vote_accounts` that pass the filter.
+    pub fn vote_pubkeys<'a>(&self, vote_accounts: &'a RpcVoteAccountStatus) -> HashSet<&'a str> {
+        let mut ranked: Vec<_> = vote_accounts
+            .current
+            .iter()
+            .chain(vote_accounts.delinquent.iter())
+            .collect();
+        // Break ties by pubkey so that ranks are stable between iterations.
+        ranked.sort_by(|a, b| {
+            b.activated_stake
+                .cmp(&a.activated_stake)
+                .then_with(|| a.vote_pubkey.cmp(&b.vote_pubkey))
+        });
+        let ranked = ranked.into_iter().map(|v| v.vote_pubkey.as_str());
+        match *self {
+            RankFilter::Top(n) => ranked.take(n).collect(),
+            RankFilter::OutsideTop(n) => ranked.skip(n).collect(),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ExporterConfig {
     /// Solana RPC address.
     pub rpc: String,
+    /// Commitment level of RPC queries.
+    #[serde(default)]
+    pub commitment: Commitment,
     /// Prometheus target socket address.
     pub target: SocketAddr,
+    /// Path of the PEM-encoded TLS certificate of the metrics endpoint. Metrics are served over
+    /// HTTPS iff both this and `tls_key_path` are set.
+    pub tls_cert_path: Option<PathBuf>,
+    /// Path of the PEM-encoded TLS private key of the metrics endpoint.
+    pub tls_key_path: Option<PathBuf>,
+    /// Username that scrapes of the metrics endpoint must authenticate with using HTTP basic auth.
+    /// The endpoint is open if either this or `metrics_password` is not set.
+    pub metrics_username: Option<String>,
+    /// Password that scrapes of the metrics endpoint must authenticate with using HTTP basic auth.
+    pub metrics_password: Option<String>,
+    /// Interval between iterations of the main loop in seconds.
+    #[serde(default = "default_scrape_interval_seconds")]
+    pub scrape_interval_seconds: u64,
+    /// Whether to geolocate validators at all. Allows disabling geolocation without removing the
+    /// MaxMind configuration.
+    #[serde(default = "default_enable_geolocation")]
+    pub enable_geolocation: bool,
+    /// Minimum interval between geolocation updates in seconds. Updated every iteration if not set.
+    pub geolocation_interval_seconds: Option<u64>,
+    /// Socket address of the liveness endpoint. Not served if not set.
+    pub health_target: Option<SocketAddr>,
+    /// Maximum age in seconds of the last successful loop iteration for the exporter to be healthy.
+    #[serde(default = "default_health_max_age_seconds")]
+    pub health_max_age_seconds: u64,
+    /// Maximum number of retries of a failed RPC call in the main loop.
+    #[serde(default = "default_rpc_max_retries")]
+    pub rpc_max_retries: u32,
+    /// Timeout of a single RPC request in seconds.
+    #[serde(default = "default_rpc_timeout_seconds")]
+    pub rpc_timeout_seconds: u64,
+    /// Maximum number of accounts fetched in a single `getMultipleAccounts` RPC call.
+    #[serde(default = "default_account_fetch_chunk_size")]
+    pub account_fetch_chunk_size: usize,
+    /// Node identity pubkeys whose balances are always exported, whether or not they are whitelisted
+    /// or in the cluster.
+    pub identity_pubkeys: Option<Whitelist>,
     /// Whitelisted vote account pubkeys.
     pub vote_account_whitelist: Option<Whitelist>,
+    /// Newline-delimited file of further whitelisted vote account pubkeys, re-read when it changes.
+    pub pubkey_whitelist_file: Option<PathBuf>,
     /// Whitelisted staking account pubkeys for APY calculation
     pub staking_account_whitelist: Option<Whitelist>,
+    /// Number of days cached geolocation data is kept, either uniform or tiered by stake.
+    #[serde(default)]
+    pub geo_cache_ttl_days: GeoCacheTtl,
+    /// Number of past epochs of rewards data to keep in the database. Kept forever if not set.
+    pub rewards_retention_epochs: Option<u64>,
+    /// Location of a separate persistent database for the geolocation cache. Kept in the shared
+    /// database if not set.
+    pub geolocation_db_path: Option<PathBuf>,
+    /// Location of a separate persistent database for the rewards cache. Kept in the shared database
+    /// if not set.
+    pub rewards_db_path: Option<PathBuf>,
+    /// Number of epochs over which the average APY is calculated, inclusive of the current epoch.
+    #[serde(default = "default_apy_lookback_epochs")]
+    pub apy_lookback_epochs: u64,
+    /// Number of recent leader slots per validator over which the rolling skip rate is
+    /// calculated. Not calculated if not set.
+    pub skip_rate_window_slots: Option<usize>,
+    /// Number of most recent blocks of the current epoch over which block times are sampled for
+    /// the slot time histogram. Not sampled if not set.
+    pub slot_time_sample_blocks: Option<usize>,
+    /// Whether to export the staking APY net of inflation dilution.
+    #[serde(default)]
+    pub real_staking_apy: bool,
+    /// Whether to export the supply and the inflation rate of the cluster, which takes additional
+    /// RPC calls.
+    #[serde(default)]
+    pub track_supply: bool,
+    /// Whether to export the balances of vote accounts and whether they are rent-exempt, which takes
+    /// additional RPC calls.
+    #[serde(default)]
+    pub export_vote_account_balances: bool,
+    /// Whether to export the activating and deactivating stake and the number of stake accounts of
+    /// validators, which requires fetching all stake accounts.
+    #[serde(default)]
+    pub track_stake_states: bool,
+    /// Whether to export changes of the authorities of vote accounts, which requires fetching all
+    /// whitelisted vote accounts.
+    #[serde(default)]
+    pub track_vote_authorities: bool,
+    /// Whether to export the standard process metrics of the exporter. Only available on Linux.
+    #[serde(default)]
+    pub track_process_metrics: bool,
+    /// Path of a local MaxMind City database. Preferred over the MaxMind API if set.
+    pub maxmind_db_path: Option<PathBuf>,
+    /// Path of a local MaxMind ASN database, used together with `maxmind_db_path`.
+    pub maxmind_asn_db_path: Option<PathBuf>,
+    /// Path of a local City database consulted for IP addresses that could not be located to a
+    /// country and city.
+    pub geolocation_fallback_db_path: Option<PathBuf>,
+    /// Timeout of MaxMind API requests in seconds.
+    #[serde(default = "default_maxmind_timeout_seconds")]
+    pub maxmind_timeout_seconds: u64,
+    /// Maximum number of concurrent requests to the MaxMind API.
+    #[serde(default = "default_maxmind_max_concurrent")]
+    pub maxmind_max_concurrent: usize,
+    /// File containing the password of the MaxMind API key, which takes precedence over the
+    /// password in `maxmind`.
+    pub maxmind_password_file: Option<PathBuf>,
+    /// URL of an HTTP, HTTPS or SOCKS5 proxy for MaxMind API requests. Not used for RPC requests.
+    pub http_proxy: Option<String>,
+    /// Whether to export the location of the RPC node. Requires geolocation to be configured.
+    #[serde(default)]
+    pub export_rpc_location: bool,
     /// Maxmind API username and password.
     pub maxmind: Option<MaxMindAPIKey>,
+    /// Prefix of every metric name, e.g., `mainnet_`. Metrics are not prefixed by default.
+    #[serde(default)]
+    pub metric_prefix: String,
+    /// Salt used to hash pubkey label values. Pubkeys are exported as-is if not set.
+    pub pubkey_label_salt: Option<String>,
+    /// Mapping of metric names to additional names under which the same metrics are exported.
+    #[serde(default)]
+    pub shadow_metrics: HashMap<String, String>,
+    /// Mapping of ASNs and country codes to regions.
+    #[serde(default)]
+    pub regions: RegionMapping,
+    /// Mapping of ASNs to datacenter names that replace the datacenter identifiers of those ASNs.
+    #[serde(default)]
+    pub datacenter_overrides: HashMap<String, String>,
+    /// Filter of validators by stake rank for which per-validator series are exported.
+    pub rank_filter: Option<RankFilter>,
+    /// Webhook to which a summary is posted at the end of every epoch.
+    pub webhook: Option<WebhookConfig>,
+}
+
+impl ExporterConfig {
+    /// Checks the config for problems that deserialization does not catch. Returns a description
+    /// of every problem found.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = vec![];
+
+        if let Err(e) = reqwest::Url::parse(&self.rpc) {
+            errors.push(format!("`rpc` is not a valid URL: {}", e));
+        }
+
+        // Addresses are not bound here, since they are in use while the exporter is running, e.g.,
+        // when checking its config. Binding errors are reported when the servers start.
+        if self.health_target == Some(self.target) {
+            errors.push("`health_target` must differ from `target`".to_string());
+        }
+
+        match (&self.tls_cert_path, &self.tls_key_path) {
+            (Some(_), None) | (None, Some(_)) => {
+                errors.push("`tls_cert_path` and `tls_key_path` must be set together".to_string())
+            }
+            (Some(cert_path), Some(key_path)) => {
+                for (name, path) in
+                    [("tls_cert_path", cert_path), ("tls_key_path", key_path)].iter()
+                {
+                    if !path.is_file() {
+                        errors.push(format!("`{}` {:?} is not a file", name, path));
+                    }
+                }
+            }
+            (None, None) => {}
+        }
+
+        if let Some(path) = &self.pubkey_whitelist_file {
+            if !path.is_file() {
+                errors.push(format!("`pubkey_whitelist_file` {:?} is not a file", path));
+            }
+        }
+
+        if self.metrics_username.is_some() != self.metrics_password.is_some() {
+            errors
+                .push("`metrics_username` and `metrics_password` must be set together".to_string());
+        }
+
+        for (name, whitelist) in [
+            ("vote_account_whitelist", &self.vote_account_whitelist),
+            ("staking_account_whitelist", &self.staking_account_whitelist),
+            ("identity_pubkeys", &self.identity_pubkeys),
+        ]
+        .iter()
+        {
+            for pubkey in whitelist.iter().flat_map(|w| w.0.iter()) {
+                if Pubkey::from_str(pubkey).is_err() {
+                    errors.push(format!("`{}` contains an invalid pubkey {}", name, pubkey));
+                }
+            }
+        }
+
+        if let Some(http_proxy) = &self.http_proxy {
+            if let Err(e) = reqwest::Proxy::all(http_proxy) {
+                errors.push(format!("`http_proxy` is not a valid proxy URL: {}", e));
+            }
+        }
+
+        if self.account_fetch_chunk_size < 1 {
+            errors.push("`account_fetch_chunk_size` must be at least 1".to_string());
+        }
+
+        // Larger chunks are only accepted by RPC nodes with a raised limit, which the public ones
+        // do not have.
+        let public_rpc = reqwest::Url::parse(&self.rpc)
+            .ok()
+            .and_then(|url| url.host_str().map(|host| PUBLIC_RPC_HOSTS.contains(&host)))
+            .unwrap_or(false);
+        if public_rpc && self.account_fetch_chunk_size > DEFAULT_ACCOUNT_FETCH_CHUNK_SIZE {
+            errors.push(format!(
+                "`account_fetch_chunk_size` must be at most {} for the public RPC node {}",
+                DEFAULT_ACCOUNT_FETCH_CHUNK_SIZE, self.rpc
+            ));
+        }
+
+        if self.rpc_timeout_seconds < 1 {
+            errors.push("`rpc_timeout_seconds` must be at least 1".to_string());
+        }
+
+        if self
+            .slot_time_sample_blocks
+            .map_or(false, |blocks| blocks < 2)
+        {
+            errors.push("`slot_time_sample_blocks` must be at least 2".to_string());
+        }
+
+        if self.maxmind_max_concurrent < 1 {
+            errors.push("`maxmind_max_concurrent` must be at least 1".to_string());
+        }
+
+        if self.apy_lookback_epochs < 1 {
+            errors.push("`apy_lookback_epochs` must be at least 1".to_string());
+        }
+
+        for asn in self.datacenter_overrides.keys() {
+            if asn.parse::<u32>().is_err() {
+                errors.push(format!(
+                    "`datacenter_overrides` contains an invalid ASN {}",
+                    asn
+                ));
+            }
+        }
+
+        let valid_prefix = self.metric_prefix.chars().enumerate().all(|(i, c)| {
+            c.is_ascii_alphabetic() || c == '_' || c == ':' || (i > 0 && c.is_ascii_digit())
+        });
+        if !valid_prefix {
+            errors.push(format!(
+                "`metric_prefix` {} is not a valid metric name prefix",
+                self.metric_prefix
+            ));
+        }
+
+        if let Some(maxmind) = &self.maxmind {
+            if maxmind.username().is_empty() || maxmind.password().is_empty() {
+                errors.push("`maxmind` username and password must not be empty".to_string());
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
 }
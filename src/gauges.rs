@@ -1,24 +1,45 @@
-use crate::config::Whitelist;
+use crate::config::{ExporterConfig, RankFilter, Whitelist};
 use crate::geolocation::api::MaxMindAPIKey;
 use crate::geolocation::api::MAXMIND_CITY_URI;
 use crate::geolocation::caching::GeolocationCache;
-use crate::geolocation::get_rpc_contact_ip;
 use crate::geolocation::identifier::DatacenterIdentifier;
-use crate::rpc_extra::with_first_block;
+use crate::geolocation::local::LocalGeolocationDatabase;
+use crate::geolocation::{
+    get_primary_contact_ip, is_public, GeolocationBackend, GeolocationSources,
+};
+use crate::rpc_extra::{
+    block_config, client_name, error_kind, with_first_block, MIN_EXTRAPOLATION_SLOTS,
+};
+use crate::shadow::ShadowCollector;
+use crate::SOLANA_EXPORTER_VERSION;
 use anyhow::{anyhow, Context};
-use futures::TryFutureExt;
+use futures::{future, Future, StreamExt, TryFutureExt};
 use geoip2_city::CityApiResponse;
-use log::{debug, error};
+use log::{debug, error, warn};
+use prometheus_exporter::prometheus::core::Collector;
+#[cfg(target_os = "linux")]
+use prometheus_exporter::prometheus::process_collector::ProcessCollector;
 use prometheus_exporter::prometheus::{
-    register_gauge, register_gauge_vec, register_int_counter_vec, register_int_gauge,
-    register_int_gauge_vec, Gauge, GaugeVec, IntCounterVec, IntGauge, IntGaugeVec,
+    register_gauge, register_gauge_vec, register_histogram, register_int_counter,
+    register_int_counter_vec, register_int_gauge, register_int_gauge_vec, Gauge, GaugeVec,
+    Histogram, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
 };
+use semver::Version;
+use solana_client::client_error::ClientError;
 use solana_client::rpc_client::RpcClient;
-use solana_client::rpc_config::RpcBlockConfig;
 use solana_client::rpc_response::{RpcContactInfo, RpcVoteAccountInfo, RpcVoteAccountStatus};
 use solana_sdk::epoch_info::EpochInfo;
-use solana_transaction_status::{TransactionDetails, UiTransactionEncoding};
-use std::collections::HashMap;
+use solana_sdk::hash::hashv;
+use solana_sdk::native_token::lamports_to_sol;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::stake_history::StakeHistory;
+use solana_sdk::sysvar::stake_history;
+use solana_stake_program::stake_state::StakeState;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::net::{IpAddr, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use time::{Duration, OffsetDateTime};
 
 /// Label used for the status value
@@ -26,163 +47,826 @@ pub const STATUS_LABEL: &str = "status";
 /// Label used for public key
 pub const PUBKEY_LABEL: &str = "pubkey";
 
+/// Buckets of the slot time histogram in seconds, around the target slot time of 400 ms.
+const SLOT_TIME_BUCKETS: [f64; 10] = [0.2, 0.3, 0.4, 0.5, 0.6, 0.8, 1.0, 1.5, 2.0, 5.0];
+
+/// Maximum number of block times fetched per update for the slot time histogram.
+const MAX_SLOT_TIME_SAMPLES: usize = 8;
+
+/// Genesis hashes and names of the public clusters.
+const KNOWN_CLUSTERS: [(&str, &str); 3] = [
+    ("5eykt4UsFv8P8NJdTREpY1vzqKqZKvdpKuc147dw2N9d", "mainnet"),
+    ("4uhcVJyU9pJkvQyS88uRDiswHXSCkY3zQawwpjk2NsNY", "testnet"),
+    ("EtWTRABZaYq6iMfeYKouRu166VU2xqa1wcaWoxPkrZBG", "devnet"),
+];
+
+type RpcInfo = (RpcContactInfo, RpcVoteAccountInfo);
+type RpcInfoMaybeGeo = (RpcContactInfo, RpcVoteAccountInfo, Option<CityApiResponse>);
+type RpcInfoGeo = (RpcContactInfo, RpcVoteAccountInfo, CityApiResponse);
+
+/// Produces the values of `PUBKEY_LABEL`, optionally anonymising pubkeys by hashing them with a salt.
+#[derive(Clone, Debug, Default)]
+pub struct PubkeyLabels {
+    salt: Option<String>,
+}
+
+impl PubkeyLabels {
+    /// Makes a new label mapping. Pubkeys are hashed iff `salt` is set.
+    pub fn new(salt: Option<String>) -> Self {
+        Self { salt }
+    }
+
+    /// Returns the label value for `pubkey`: either the pubkey itself or the base58-encoded SHA-256
+    /// hash of the salt followed by the pubkey.
+    pub fn label(&self, pubkey: &str) -> String {
+        match &self.salt {
+            Some(salt) => hashv(&[salt.as_bytes(), pubkey.as_bytes()]).to_string(),
+            None => pubkey.to_string(),
+        }
+    }
+}
+
 pub struct PrometheusGauges {
     pub active_validators: IntGaugeVec,
+    pub current_stake: IntGauge,
+    pub delinquent_stake: IntGauge,
     pub is_delinquent: GaugeVec,
     pub activated_stake: IntGaugeVec,
+    pub vote_account_balance: IntGaugeVec,
+    pub vote_account_rent_exempt: IntGaugeVec,
+    pub activating_stake: IntGaugeVec,
+    pub deactivating_stake: IntGaugeVec,
+    pub stake_accounts: IntGaugeVec,
     pub last_vote: IntGaugeVec,
     pub root_slot: IntGaugeVec,
+    pub root_distance: IntGaugeVec,
     pub transaction_count: IntGauge,
+    pub estimated_tps: Gauge,
     pub slot_height: IntGauge,
     pub current_epoch: IntGauge,
     pub current_epoch_first_slot: IntGauge,
     pub current_epoch_last_slot: IntGauge,
     pub isp_count: IntGaugeVec,
+    pub geo_info: IntGaugeVec,
     pub isp_by_stake: IntGaugeVec,
     pub dc_by_stake: IntGaugeVec,
+    pub stake_by_region: IntGaugeVec,
+    pub stake_by_country: IntGaugeVec,
+    pub stake_by_continent: IntGaugeVec,
+    pub nakamoto_coefficient: IntGauge,
+    pub stake_gini: Gauge,
     pub leader_slots: IntCounterVec,
     pub skipped_slot_percent: GaugeVec,
+    pub skip_rate_rank: GaugeVec,
+    pub cluster_skipped_slot_percent: Gauge,
+    pub skip_rate_rolling: GaugeVec,
+    pub leader_slot_gap_avg: GaugeVec,
+    pub leader_slot_gap_max: IntGaugeVec,
+    pub skipped_slots_scanned_total: IntCounter,
+    pub skipped_slots_last_scan: IntGauge,
+    pub blocks_produced: IntGaugeVec,
+    pub blocks_expected: IntGaugeVec,
+    pub leader_slots_scheduled: IntGaugeVec,
     pub current_staking_apy: GaugeVec,
     pub average_staking_apy: GaugeVec,
+    pub apy_epoch: IntGaugeVec,
+    pub real_staking_apy: GaugeVec,
     pub staking_commission: IntGaugeVec,
     pub validator_rewards: IntGaugeVec,
+    pub epoch_reward_entries: IntGaugeVec,
+    pub delegator_count: IntGaugeVec,
     pub node_pubkey_balances: IntGaugeVec,
+    pub is_current_leader: IntGaugeVec,
+    pub identity_balance_sol: GaugeVec,
     pub node_versions: IntGaugeVec,
+    pub version_behind: IntGaugeVec,
+    pub cluster_clients: IntGaugeVec,
+    pub cluster_client_stake: IntGaugeVec,
     pub nodes: IntGauge,
+    pub cluster_nodes_total: IntGauge,
     pub average_slot_time: Gauge,
+    pub slot_time: Histogram,
+    pub total_supply_sol: Gauge,
+    pub circulating_supply_sol: Gauge,
+    pub inflation_rate: Gauge,
+    pub loops_since_epoch_change: IntGauge,
+    pub uptime: Gauge,
+    pub build_info: IntGaugeVec,
+    pub cluster_info: IntGaugeVec,
+    pub vote_account_identity_changed: IntCounterVec,
+    pub commission_changed: IntGaugeVec,
+    pub vote_authority_changed: IntGaugeVec,
+    pub epoch_credits: IntGaugeVec,
+    pub credits_percentile: GaugeVec,
+    pub performance_discrepancy: GaugeVec,
+    pub empty_response: IntCounterVec,
+    pub rpc_errors: IntCounterVec,
+    pub scrape_duration: Gauge,
+    pub config_mtime: IntGauge,
+    pub cached_reward_epochs: IntGauge,
+    pub rewards_available: IntGauge,
+    pub rpc_location: IntGaugeVec,
+    pub geolocation_fallback: IntCounter,
+    pub maxmind_lookups: IntCounter,
+    pub geolocation_cache_hits: IntCounter,
+    pub last_geolocation_refresh: IntGauge,
+    pub whitelist_entries: IntGauge,
+    pub whitelist_matched: IntGauge,
+    pub rpc_call_duration: GaugeVec,
+    pub rpc_healthy: IntGauge,
+    pub rpc_version: IntGaugeVec,
+    /// Mapping of pubkeys to label values shared with the monitors.
+    pub pubkey_labels: PubkeyLabels,
     // Connection pool for querying
     client: reqwest::Client,
-    vote_accounts_whitelist: Whitelist,
+    vote_accounts_whitelist: RwLock<Whitelist>,
+    rank_filter: Option<RankFilter>,
+    /// Prefix of every metric name.
+    metric_prefix: String,
+    /// Transaction count and time of the previous update, from which the TPS is estimated.
+    last_transaction_count: Mutex<Option<(u64, Instant)>>,
+    /// Slot of the last block sampled for the slot time histogram.
+    last_slot_time_sample: Mutex<Option<u64>>,
 }
 
 impl PrometheusGauges {
     /// Makes new set of gauges.
-    pub fn new(vote_accounts_whitelist: Whitelist) -> Self {
+    pub fn new(
+        vote_accounts_whitelist: Whitelist,
+        pubkey_labels: PubkeyLabels,
+        rank_filter: Option<RankFilter>,
+        client: reqwest::Client,
+        metric_prefix: String,
+        track_process_metrics: bool,
+    ) -> Self {
+        if track_process_metrics {
+            register_process_collector();
+        }
+
+        // Every metric name is prefixed, so that exporters of different clusters can be told apart.
+        let metric_name = |name: &str| format!("{}{}", metric_prefix, name);
+        let build_info = register_int_gauge_vec!(
+            metric_name("solana_exporter_build_info"),
+            "Build information of the exporter, always 1",
+            &["version", "solana_client", "rustc", "git_commit"]
+        )
+        .unwrap();
+        build_info
+            .with_label_values(&[
+                SOLANA_EXPORTER_VERSION,
+                option_env!("SOLANA_EXPORTER_SOLANA_CLIENT").unwrap_or("unknown"),
+                option_env!("SOLANA_EXPORTER_RUSTC").unwrap_or("unknown"),
+                option_env!("SOLANA_EXPORTER_GIT_COMMIT").unwrap_or("unknown"),
+            ])
+            .set(1);
+
         Self {
+            cluster_info: register_int_gauge_vec!(
+                metric_name("solana_cluster_info"),
+                "Genesis hash and name of the cluster, always 1",
+                &["genesis_hash", "cluster"]
+            )
+            .unwrap(),
             active_validators: register_int_gauge_vec!(
-                "solana_active_validators",
+                metric_name("solana_active_validators"),
                 "Total number of active validators",
                 &[STATUS_LABEL]
             )
             .unwrap(),
+            current_stake: register_int_gauge!(
+                metric_name("solana_current_stake"),
+                "Total activated stake of current validators"
+            )
+            .unwrap(),
+            delinquent_stake: register_int_gauge!(
+                metric_name("solana_delinquent_stake"),
+                "Total activated stake of delinquent validators"
+            )
+            .unwrap(),
             is_delinquent: register_gauge_vec!(
-                "solana_validator_delinquent",
+                metric_name("solana_validator_delinquent"),
                 "Whether a validator is delinquent",
                 &[PUBKEY_LABEL]
             )
             .unwrap(),
             activated_stake: register_int_gauge_vec!(
-                "solana_validator_activated_stake",
+                metric_name("solana_validator_activated_stake"),
                 "Activated stake of a validator",
                 &[PUBKEY_LABEL]
             )
             .unwrap(),
+            vote_account_balance: register_int_gauge_vec!(
+                metric_name("solana_vote_account_balance"),
+                "Balance of a vote account in lamports",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
+            vote_account_rent_exempt: register_int_gauge_vec!(
+                metric_name("solana_vote_account_rent_exempt"),
+                "Whether the balance of a vote account is at least the rent-exempt minimum",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
+            activating_stake: register_int_gauge_vec!(
+                metric_name("solana_validator_activating_stake"),
+                "Stake of a validator that is still activating",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
+            deactivating_stake: register_int_gauge_vec!(
+                metric_name("solana_validator_deactivating_stake"),
+                "Stake of a validator that is deactivating",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
+            stake_accounts: register_int_gauge_vec!(
+                metric_name("solana_validator_stake_accounts"),
+                "Number of active, activating or deactivating stake accounts delegated to the vote account",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
             last_vote: register_int_gauge_vec!(
-                "solana_validator_last_vote",
+                metric_name("solana_validator_last_vote"),
                 "Last voted slot of a validator",
                 &[PUBKEY_LABEL]
             )
             .unwrap(),
             root_slot: register_int_gauge_vec!(
-                "solana_validator_root_slot",
+                metric_name("solana_validator_root_slot"),
                 "The root slot of a validator",
                 &[PUBKEY_LABEL]
             )
             .unwrap(),
+            root_distance: register_int_gauge_vec!(
+                metric_name("solana_validator_root_distance"),
+                "Number of slots the root slot of a validator is behind the current slot",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
             transaction_count: register_int_gauge!(
-                "solana_transaction_count",
+                metric_name("solana_transaction_count"),
                 "Total number of confirmed transactions since genesis"
             )
             .unwrap(),
-            slot_height: register_int_gauge!("solana_slot_height", "Last confirmed slot height")
+            estimated_tps: register_gauge!(
+                metric_name("solana_estimated_tps"),
+                "Transactions per second since the previous update"
+            )
+            .unwrap(),
+            slot_height: register_int_gauge!(metric_name("solana_slot_height"), "Last confirmed slot height")
                 .unwrap(),
-            current_epoch: register_int_gauge!("solana_current_epoch", "Current epoch").unwrap(),
+            current_epoch: register_int_gauge!(metric_name("solana_current_epoch"), "Current epoch").unwrap(),
             current_epoch_first_slot: register_int_gauge!(
-                "solana_current_epoch_first_slot",
+                metric_name("solana_current_epoch_first_slot"),
                 "Current epoch's first slot"
             )
             .unwrap(),
             current_epoch_last_slot: register_int_gauge!(
-                "solana_current_epoch_last_slot",
+                metric_name("solana_current_epoch_last_slot"),
                 "Current epoch's last slot"
             )
             .unwrap(),
             isp_count: register_int_gauge_vec!(
-                "solana_active_validators_isp_count",
+                metric_name("solana_active_validators_isp_count"),
                 "ISP of active validators",
                 &["isp_name"]
             )
             .unwrap(),
+            geo_info: register_int_gauge_vec!(
+                metric_name("solana_validator_geo_info"),
+                "Geolocation of a validator node, always 1",
+                &[PUBKEY_LABEL, "country", "city", "isp", "asn"]
+            )
+            .unwrap(),
             isp_by_stake: register_int_gauge_vec!(
-                "solana_active_validators_isp_stake",
+                metric_name("solana_active_validators_isp_stake"),
                 "ISP of active validators grouped by stake",
                 &["isp_name"]
             )
             .unwrap(),
             dc_by_stake: register_int_gauge_vec!(
-                "solana_active_validators_dc_stake",
+                metric_name("solana_active_validators_dc_stake"),
                 "Datacenter of active validators grouped by stake",
                 &["dc_identifier"]
             )
             .unwrap(),
+            stake_by_region: register_int_gauge_vec!(
+                metric_name("solana_stake_by_region"),
+                "Activated stake of validators grouped by configured region",
+                &["region"]
+            )
+            .unwrap(),
+            stake_by_country: register_int_gauge_vec!(
+                metric_name("solana_stake_by_country"),
+                "Activated stake of validators grouped by ISO-3166 country code",
+                &["country"]
+            )
+            .unwrap(),
+            stake_by_continent: register_int_gauge_vec!(
+                metric_name("solana_stake_by_continent"),
+                "Activated stake of validators grouped by continent code",
+                &["continent"]
+            )
+            .unwrap(),
+            nakamoto_coefficient: register_int_gauge!(
+                metric_name("solana_nakamoto_coefficient"),
+                "Minimum number of validators controlling more than a third of the activated stake"
+            )
+            .unwrap(),
+            stake_gini: register_gauge!(
+                metric_name("solana_stake_gini"),
+                "Gini coefficient of the activated stake of validators"
+            )
+            .unwrap(),
             leader_slots: register_int_counter_vec!(
-                "solana_leader_slots",
+                metric_name("solana_leader_slots"),
                 "Validated and skipped leader slots per validator",
                 &[PUBKEY_LABEL, STATUS_LABEL]
             )
             .unwrap(),
             skipped_slot_percent: register_gauge_vec!(
-                "solana_skipped_slot_percent",
+                metric_name("solana_skipped_slot_percent"),
                 "Skipped slot percentage per validator",
                 &[PUBKEY_LABEL]
             )
             .unwrap(),
+            skip_rate_rank: register_gauge_vec!(
+                metric_name("solana_validator_skip_rate_rank"),
+                "Percentage of leaders in the current epoch that skipped at most the same percentage of slots as a validator",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
+            cluster_skipped_slot_percent: register_gauge!(
+                metric_name("solana_cluster_skipped_slot_percent"),
+                "Skipped slot percentage over all leaders in the current epoch"
+            )
+            .unwrap(),
+            skip_rate_rolling: register_gauge_vec!(
+                metric_name("solana_validator_skip_rate_rolling"),
+                "Skipped slot percentage over a fixed number of recent leader slots",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
+            leader_slot_gap_avg: register_gauge_vec!(
+                metric_name("solana_validator_leader_slot_gap_avg"),
+                "Average number of slots between leader rotations of a validator in the current epoch",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
+            leader_slot_gap_max: register_int_gauge_vec!(
+                metric_name("solana_validator_leader_slot_gap_max"),
+                "Maximum number of slots between leader rotations of a validator in the current epoch",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
+            skipped_slots_scanned_total: register_int_counter!(
+                metric_name("solana_skipped_slots_scanned_total"),
+                "Cumulative number of slots scanned for skipped slot statistics"
+            )
+            .unwrap(),
+            skipped_slots_last_scan: register_int_gauge!(
+                metric_name("solana_skipped_slots_last_scan"),
+                "Number of slots scanned for skipped slot statistics in the last iteration"
+            )
+            .unwrap(),
+            blocks_produced: register_int_gauge_vec!(
+                metric_name("solana_validator_blocks_produced"),
+                "Number of blocks produced by a validator in its leader slots in the current epoch",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
+            blocks_expected: register_int_gauge_vec!(
+                metric_name("solana_validator_blocks_expected"),
+                "Number of leader slots of a validator that have elapsed in the current epoch",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
+            leader_slots_scheduled: register_int_gauge_vec!(
+                metric_name("solana_validator_leader_slots_scheduled"),
+                "Number of leader slots of a validator in the current epoch",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
             current_staking_apy: register_gauge_vec!(
-                "solana_current_staking_apy",
+                metric_name("solana_current_staking_apy"),
                 "Staking validator APY based on last epoch's performance, in percent",
                 &[PUBKEY_LABEL]
             )
             .unwrap(),
             average_staking_apy: register_gauge_vec!(
-                "solana_average_staking_apy",
+                metric_name("solana_average_staking_apy"),
                 "Staking validator APY averaged over a few past epochs, in percent",
                 &[PUBKEY_LABEL]
             )
             .unwrap(),
+            apy_epoch: register_int_gauge_vec!(
+                metric_name("solana_validator_apy_epoch"),
+                "Epoch for which the staking APY of a validator was last calculated",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
+            real_staking_apy: register_gauge_vec!(
+                metric_name("solana_real_staking_apy"),
+                "Staking validator APY based on last epoch's performance net of inflation dilution, in percent",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
             staking_commission: register_int_gauge_vec!(
-                "solana_staking_commission",
+                metric_name("solana_staking_commission"),
                 "Commission charged by staked validators",
                 &[PUBKEY_LABEL]
             )
             .unwrap(),
             validator_rewards: register_int_gauge_vec!(
-                "solana_validator_rewards",
+                metric_name("solana_validator_rewards"),
                 "Cumulative validator rewards in lamports",
                 &[PUBKEY_LABEL]
             )
             .unwrap(),
+            epoch_reward_entries: register_int_gauge_vec!(
+                metric_name("solana_epoch_reward_entries"),
+                "Number of rewards found in the first block of an epoch",
+                &["epoch"]
+            )
+            .unwrap(),
+            delegator_count: register_int_gauge_vec!(
+                metric_name("solana_validator_delegator_count"),
+                "Number of stake accounts delegated to the vote account that earned staking rewards in the epoch",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
             node_pubkey_balances: register_int_gauge_vec!(
-                "solana_node_pubkey_balances",
+                metric_name("solana_node_pubkey_balances"),
                 "Balance of node pubkeys",
                 &[PUBKEY_LABEL]
             )
             .unwrap(),
+            is_current_leader: register_int_gauge_vec!(
+                metric_name("solana_validator_is_current_leader"),
+                "Whether a node is the leader of the current slot",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
+            identity_balance_sol: register_gauge_vec!(
+                metric_name("solana_identity_balance_sol"),
+                "Balance of node pubkeys in SOL",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
             node_versions: register_int_gauge_vec!(
-                "solana_node_versions",
+                metric_name("solana_node_versions"),
                 "Count of node versions",
                 &["version"]
             )
             .unwrap(),
-            nodes: register_int_gauge!("solana_nodes", "Number of nodes").unwrap(),
-            average_slot_time: register_gauge!("solana_average_slot_time", "Average slot time")
+            version_behind: register_int_gauge_vec!(
+                metric_name("solana_validator_version_behind"),
+                "Number of minor versions a node is behind the highest version on the cluster",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
+            cluster_clients: register_int_gauge_vec!(
+                metric_name("solana_cluster_clients"),
+                "Number of nodes in the cluster by validator client software",
+                &["client"]
+            )
+            .unwrap(),
+            cluster_client_stake: register_int_gauge_vec!(
+                metric_name("solana_cluster_client_stake"),
+                "Activated stake of nodes in the cluster by validator client software, in lamports",
+                &["client"]
+            )
+            .unwrap(),
+            nodes: register_int_gauge!(metric_name("solana_nodes"), "Number of nodes").unwrap(),
+            cluster_nodes_total: register_int_gauge!(
+                metric_name("solana_cluster_nodes_total"),
+                "Number of nodes in the cluster, regardless of the whitelist"
+            )
+            .unwrap(),
+            average_slot_time: register_gauge!(metric_name("solana_average_slot_time"), "Average slot time")
+                .unwrap(),
+            slot_time: register_histogram!(
+                metric_name("solana_slot_time_seconds"),
+                "Time per slot between consecutive recent blocks in seconds",
+                SLOT_TIME_BUCKETS.to_vec()
+            )
+            .unwrap(),
+            total_supply_sol: register_gauge!(metric_name("solana_total_supply_sol"), "Total supply in SOL")
                 .unwrap(),
-            client: reqwest::Client::new(),
-            vote_accounts_whitelist,
+            circulating_supply_sol: register_gauge!(
+                metric_name("solana_circulating_supply_sol"),
+                "Circulating supply in SOL"
+            )
+            .unwrap(),
+            inflation_rate: register_gauge!(
+                metric_name("solana_inflation_rate"),
+                "Total yearly inflation rate of the current epoch"
+            )
+            .unwrap(),
+            loops_since_epoch_change: register_int_gauge!(
+                metric_name("solana_loops_since_epoch_change"),
+                "Number of exporter loop iterations since the last epoch change"
+            )
+            .unwrap(),
+            uptime: register_gauge!(
+                metric_name("solana_exporter_uptime_seconds"),
+                "Number of seconds since the exporter started"
+            )
+            .unwrap(),
+            build_info,
+            vote_account_identity_changed: register_int_counter_vec!(
+                metric_name("solana_vote_account_identity_changed_total"),
+                "Number of times a vote account was observed to change its node pubkey",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
+            commission_changed: register_int_gauge_vec!(
+                metric_name("solana_validator_commission_changed"),
+                "Change of the commission of a vote account since the previous epoch, in percentage points",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
+            vote_authority_changed: register_int_gauge_vec!(
+                metric_name("solana_vote_authority_changed"),
+                "Whether an authority of a vote account changed since the previous epoch, 0 or 1",
+                &[PUBKEY_LABEL, "authority"]
+            )
+            .unwrap(),
+            epoch_credits: register_int_gauge_vec!(
+                metric_name("solana_validator_epoch_credits"),
+                "Vote credits earned by a validator in the current epoch",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
+            credits_percentile: register_gauge_vec!(
+                metric_name("solana_validator_credits_percentile"),
+                "Percentage of active validators that earned at most as many vote credits as a validator in the current epoch",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
+            performance_discrepancy: register_gauge_vec!(
+                metric_name("solana_validator_performance_discrepancy"),
+                "Vote success rate minus block production rate of a validator in the current epoch",
+                &[PUBKEY_LABEL]
+            )
+            .unwrap(),
+            empty_response: register_int_counter_vec!(
+                metric_name("solana_exporter_empty_response_total"),
+                "Number of semantically empty RPC responses for which exporting was skipped",
+                &["method"]
+            )
+            .unwrap(),
+            rpc_errors: register_int_counter_vec!(
+                metric_name("solana_exporter_rpc_errors_total"),
+                "Number of failed RPC calls, including those that were retried",
+                &["method", "kind"]
+            )
+            .unwrap(),
+            scrape_duration: register_gauge!(
+                metric_name("solana_exporter_scrape_duration_seconds"),
+                "Duration of the last exporter loop iteration in seconds"
+            )
+            .unwrap(),
+            config_mtime: register_int_gauge!(
+                metric_name("solana_exporter_config_mtime"),
+                "Last modification time of the config file loaded at startup as a Unix timestamp"
+            )
+            .unwrap(),
+            cached_reward_epochs: register_int_gauge!(
+                metric_name("solana_exporter_cached_reward_epochs"),
+                "Number of epochs whose rewards are cached in the database"
+            )
+            .unwrap(),
+            rewards_available: register_int_gauge!(
+                metric_name("solana_exporter_rewards_available"),
+                "Whether the rewards of the current epoch are available, 0 or 1"
+            )
+            .unwrap(),
+            rpc_location: register_int_gauge_vec!(
+                metric_name("solana_exporter_rpc_location"),
+                "Location of the RPC node the exporter queries, always 1",
+                &["datacenter", "region"]
+            )
+            .unwrap(),
+            geolocation_fallback: register_int_counter!(
+                metric_name("solana_exporter_geolocation_fallback_total"),
+                "Number of geolocations completed from the fallback database"
+            )
+            .unwrap(),
+            maxmind_lookups: register_int_counter!(
+                metric_name("solana_exporter_maxmind_lookups_total"),
+                "Number of requests to the MaxMind web API"
+            )
+            .unwrap(),
+            geolocation_cache_hits: register_int_counter!(
+                metric_name("solana_exporter_geolocation_cache_hits_total"),
+                "Number of geolocations of the MaxMind web API found in the cache"
+            )
+            .unwrap(),
+            last_geolocation_refresh: register_int_gauge!(
+                metric_name("solana_exporter_last_geolocation_refresh_timestamp"),
+                "Unix timestamp of the last completed geolocation update"
+            )
+            .unwrap(),
+            whitelist_entries: register_int_gauge!(
+                metric_name("solana_exporter_whitelist_entries"),
+                "Number of pubkeys in the vote account whitelist"
+            )
+            .unwrap(),
+            whitelist_matched: register_int_gauge!(
+                metric_name("solana_exporter_whitelist_matched"),
+                "Number of pubkeys in the vote account whitelist that are vote accounts of the cluster"
+            )
+            .unwrap(),
+            rpc_call_duration: register_gauge_vec!(
+                metric_name("solana_exporter_rpc_call_duration_seconds"),
+                "Duration of the last RPC call of the main loop in seconds, including retries",
+                &["method"]
+            )
+            .unwrap(),
+            rpc_healthy: register_int_gauge!(
+                metric_name("solana_exporter_rpc_healthy"),
+                "Whether the RPC node the exporter queries reports itself healthy, 0 or 1"
+            )
+            .unwrap(),
+            rpc_version: register_int_gauge_vec!(
+                metric_name("solana_exporter_rpc_version"),
+                "Solana version of the RPC node the exporter queries, always 1",
+                &["version"]
+            )
+            .unwrap(),
+            pubkey_labels,
+            client,
+            vote_accounts_whitelist: RwLock::new(vote_accounts_whitelist),
+            rank_filter,
+            metric_prefix,
+            last_transaction_count: Mutex::new(None),
+            last_slot_time_sample: Mutex::new(None),
+        }
+    }
+
+    /// Returns all registered metrics.
+    fn collectors(&self) -> Vec<Box<dyn Collector>> {
+        vec![
+            Box::new(self.active_validators.clone()),
+            Box::new(self.current_stake.clone()),
+            Box::new(self.delinquent_stake.clone()),
+            Box::new(self.is_delinquent.clone()),
+            Box::new(self.activated_stake.clone()),
+            Box::new(self.vote_account_balance.clone()),
+            Box::new(self.vote_account_rent_exempt.clone()),
+            Box::new(self.activating_stake.clone()),
+            Box::new(self.deactivating_stake.clone()),
+            Box::new(self.stake_accounts.clone()),
+            Box::new(self.last_vote.clone()),
+            Box::new(self.root_slot.clone()),
+            Box::new(self.root_distance.clone()),
+            Box::new(self.transaction_count.clone()),
+            Box::new(self.estimated_tps.clone()),
+            Box::new(self.slot_height.clone()),
+            Box::new(self.current_epoch.clone()),
+            Box::new(self.current_epoch_first_slot.clone()),
+            Box::new(self.current_epoch_last_slot.clone()),
+            Box::new(self.isp_count.clone()),
+            Box::new(self.geo_info.clone()),
+            Box::new(self.isp_by_stake.clone()),
+            Box::new(self.dc_by_stake.clone()),
+            Box::new(self.stake_by_region.clone()),
+            Box::new(self.stake_by_country.clone()),
+            Box::new(self.stake_by_continent.clone()),
+            Box::new(self.nakamoto_coefficient.clone()),
+            Box::new(self.stake_gini.clone()),
+            Box::new(self.leader_slots.clone()),
+            Box::new(self.skipped_slot_percent.clone()),
+            Box::new(self.skip_rate_rank.clone()),
+            Box::new(self.cluster_skipped_slot_percent.clone()),
+            Box::new(self.skip_rate_rolling.clone()),
+            Box::new(self.leader_slot_gap_avg.clone()),
+            Box::new(self.leader_slot_gap_max.clone()),
+            Box::new(self.skipped_slots_scanned_total.clone()),
+            Box::new(self.skipped_slots_last_scan.clone()),
+            Box::new(self.blocks_produced.clone()),
+            Box::new(self.blocks_expected.clone()),
+            Box::new(self.leader_slots_scheduled.clone()),
+            Box::new(self.current_staking_apy.clone()),
+            Box::new(self.average_staking_apy.clone()),
+            Box::new(self.apy_epoch.clone()),
+            Box::new(self.real_staking_apy.clone()),
+            Box::new(self.staking_commission.clone()),
+            Box::new(self.validator_rewards.clone()),
+            Box::new(self.epoch_reward_entries.clone()),
+            Box::new(self.delegator_count.clone()),
+            Box::new(self.node_pubkey_balances.clone()),
+            Box::new(self.is_current_leader.clone()),
+            Box::new(self.identity_balance_sol.clone()),
+            Box::new(self.node_versions.clone()),
+            Box::new(self.version_behind.clone()),
+            Box::new(self.cluster_clients.clone()),
+            Box::new(self.cluster_client_stake.clone()),
+            Box::new(self.nodes.clone()),
+            Box::new(self.cluster_nodes_total.clone()),
+            Box::new(self.average_slot_time.clone()),
+            Box::new(self.slot_time.clone()),
+            Box::new(self.total_supply_sol.clone()),
+            Box::new(self.circulating_supply_sol.clone()),
+            Box::new(self.inflation_rate.clone()),
+            Box::new(self.loops_since_epoch_change.clone()),
+            Box::new(self.uptime.clone()),
+            Box::new(self.build_info.clone()),
+            Box::new(self.cluster_info.clone()),
+            Box::new(self.vote_account_identity_changed.clone()),
+            Box::new(self.commission_changed.clone()),
+            Box::new(self.vote_authority_changed.clone()),
+            Box::new(self.epoch_credits.clone()),
+            Box::new(self.credits_percentile.clone()),
+            Box::new(self.performance_discrepancy.clone()),
+            Box::new(self.empty_response.clone()),
+            Box::new(self.rpc_errors.clone()),
+            Box::new(self.scrape_duration.clone()),
+            Box::new(self.config_mtime.clone()),
+            Box::new(self.cached_reward_epochs.clone()),
+            Box::new(self.rewards_available.clone()),
+            Box::new(self.rpc_location.clone()),
+            Box::new(self.geolocation_fallback.clone()),
+            Box::new(self.maxmind_lookups.clone()),
+            Box::new(self.geolocation_cache_hits.clone()),
+            Box::new(self.last_geolocation_refresh.clone()),
+            Box::new(self.whitelist_entries.clone()),
+            Box::new(self.whitelist_matched.clone()),
+            Box::new(self.rpc_call_duration.clone()),
+            Box::new(self.rpc_healthy.clone()),
+            Box::new(self.rpc_version.clone()),
+        ]
+    }
+
+    /// Counts `result` as a failed call of the RPC `method` if it is an error, and passes it on.
+    pub fn count_rpc_error<T>(
+        &self,
+        method: &str,
+        result: Result<T, ClientError>,
+    ) -> Result<T, ClientError> {
+        if let Err(e) = &result {
+            self.rpc_errors
+                .with_label_values(&[method, error_kind(e)])
+                .inc();
+        }
+        result
+    }
+
+    /// Registers a copy of each metric in `shadow_metrics` under the name it is mapped to. The copies
+    /// always have the same values as the originals.
+    pub fn register_shadow_metrics(
+        &self,
+        shadow_metrics: &HashMap<String, String>,
+    ) -> anyhow::Result<()> {
+        let mut shadowed = HashSet::new();
+        for collector in self.collectors() {
+            // Shadow metrics are configured by unprefixed names and exported with the prefix.
+            let fq_name = &collector.desc()[0].fq_name;
+            let name = fq_name
+                .strip_prefix(&self.metric_prefix)
+                .unwrap_or(fq_name)
+                .to_string();
+            if let Some(shadow_name) = shadow_metrics.get(&name) {
+                let shadow_name = format!("{}{}", self.metric_prefix, shadow_name);
+                debug!("Shadowing {} as {}", fq_name, shadow_name);
+                prometheus_exporter::prometheus::register(Box::new(ShadowCollector::new(
+                    collector,
+                    &shadow_name,
+                )?))?;
+                shadowed.insert(name);
+            }
         }
+
+        for name in shadow_metrics
+            .keys()
+            .filter(|name| !shadowed.contains(*name))
+        {
+            warn!("Cannot shadow unknown metric {}", name);
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the whitelist of vote account pubkeys, e.g., after the whitelist file changed.
+    pub fn set_vote_accounts_whitelist(&self, whitelist: Whitelist) {
+        *self.vote_accounts_whitelist.write().unwrap() = whitelist;
     }
 
-    /// Exports gauges for vote accounts
-    pub fn export_vote_accounts(&self, vote_accounts: &RpcVoteAccountStatus) -> anyhow::Result<()> {
+    /// Returns `true` iff `vote_pubkey` is in the vote account whitelist.
+    fn is_whitelisted(&self, vote_pubkey: &str) -> bool {
+        self.vote_accounts_whitelist
+            .read()
+            .unwrap()
+            .contains(vote_pubkey)
+    }
+
+    /// Exports gauges for vote accounts. Root distances are relative to `absolute_slot`.
+    pub fn export_vote_accounts(
+        &self,
+        vote_accounts: &RpcVoteAccountStatus,
+        absolute_slot: u64,
+    ) -> anyhow::Result<()> {
         self.active_validators
             .get_metric_with_label_values(&["current"])
             .map(|m| {
@@ -190,7 +874,7 @@ impl PrometheusGauges {
                     vote_accounts
                         .current
                         .iter()
-                        .filter(|rpc| self.vote_accounts_whitelist.contains(&rpc.vote_pubkey))
+                        .filter(|rpc| self.is_whitelisted(&rpc.vote_pubkey))
                         .count() as i64,
                 )
             })?;
@@ -202,28 +886,76 @@ impl PrometheusGauges {
                     vote_accounts
                         .delinquent
                         .iter()
-                        .filter(|rpc| self.vote_accounts_whitelist.contains(&rpc.vote_pubkey))
+                        .filter(|rpc| self.is_whitelisted(&rpc.vote_pubkey))
                         .count() as i64,
                 )
             })?;
 
+        self.current_stake.set(
+            vote_accounts
+                .current
+                .iter()
+                .filter(|rpc| self.is_whitelisted(&rpc.vote_pubkey))
+                .map(|rpc| rpc.activated_stake)
+                .sum::<u64>() as i64,
+        );
+        self.delinquent_stake.set(
+            vote_accounts
+                .delinquent
+                .iter()
+                .filter(|rpc| self.is_whitelisted(&rpc.vote_pubkey))
+                .map(|rpc| rpc.activated_stake)
+                .sum::<u64>() as i64,
+        );
+
+        // Entries that are not vote accounts of the cluster, e.g., node pubkeys, match nothing.
+        {
+            let whitelist = self.vote_accounts_whitelist.read().unwrap();
+            self.whitelist_entries.set(whitelist.0.len() as i64);
+            self.whitelist_matched.set(
+                vote_accounts
+                    .current
+                    .iter()
+                    .chain(vote_accounts.delinquent.iter())
+                    .filter(|rpc| whitelist.0.contains(&rpc.vote_pubkey))
+                    .count() as i64,
+            );
+        }
+
+        // Stake concentration of the whole cluster, regardless of the whitelist
+        let stakes: Vec<u64> = vote_accounts
+            .current
+            .iter()
+            .chain(vote_accounts.delinquent.iter())
+            .map(|v| v.activated_stake)
+            .collect();
+        self.nakamoto_coefficient
+            .set(nakamoto_coefficient(stakes.clone()) as i64);
+        self.stake_gini.set(gini_coefficient(stakes));
+
+        // Per-validator series are further restricted by stake rank if configured.
+        let ranked = self.rank_filter.map(|f| f.vote_pubkeys(vote_accounts));
+        let is_exported = |pubkey: &str| {
+            self.is_whitelisted(pubkey) && ranked.as_ref().map_or(true, |r| r.contains(pubkey))
+        };
+
         for v in vote_accounts
             .current
             .iter()
-            .filter(|rpc| self.vote_accounts_whitelist.contains(&rpc.vote_pubkey))
+            .filter(|rpc| is_exported(&rpc.vote_pubkey))
         {
             self.is_delinquent
-                .get_metric_with_label_values(&[&*v.vote_pubkey])
+                .get_metric_with_label_values(&[&self.pubkey_labels.label(&v.vote_pubkey)])
                 .map(|m| m.set(0.))?;
         }
 
         for v in vote_accounts
             .delinquent
             .iter()
-            .filter(|rpc| self.vote_accounts_whitelist.contains(&rpc.vote_pubkey))
+            .filter(|rpc| is_exported(&rpc.vote_pubkey))
         {
             self.is_delinquent
-                .get_metric_with_label_values(&[&*v.vote_pubkey])
+                .get_metric_with_label_values(&[&self.pubkey_labels.label(&v.vote_pubkey)])
                 .map(|m| m.set(1.))?;
         }
 
@@ -231,25 +963,259 @@ impl PrometheusGauges {
             .current
             .iter()
             .chain(vote_accounts.delinquent.iter())
-            .filter(|rpc| self.vote_accounts_whitelist.contains(&rpc.vote_pubkey))
+            .filter(|rpc| is_exported(&rpc.vote_pubkey))
         {
+            let label = self.pubkey_labels.label(&v.vote_pubkey);
             self.activated_stake
-                .get_metric_with_label_values(&[&*v.vote_pubkey])
+                .get_metric_with_label_values(&[&label])
                 .map(|m| m.set(v.activated_stake as i64))?;
             self.last_vote
-                .get_metric_with_label_values(&[&*v.vote_pubkey])
+                .get_metric_with_label_values(&[&label])
                 .map(|m| m.set(v.last_vote as i64))?;
             self.root_slot
-                .get_metric_with_label_values(&[&*v.vote_pubkey])
+                .get_metric_with_label_values(&[&label])
                 .map(|m| m.set(v.root_slot as i64))?;
+            self.root_distance
+                .get_metric_with_label_values(&[&label])
+                .map(|m| m.set(absolute_slot.saturating_sub(v.root_slot) as i64))?;
             self.staking_commission
-                .get_metric_with_label_values(&[&*v.vote_pubkey])
+                .get_metric_with_label_values(&[&label])
                 .map(|m| m.set(v.commission as i64))?;
         }
 
         Ok(())
     }
 
+    /// Exports the balances of whitelisted vote accounts and whether they are rent-exempt.
+    pub fn export_vote_account_balances(
+        &self,
+        vote_accounts: &RpcVoteAccountStatus,
+        client: &RpcClient,
+        chunk_size: usize,
+    ) -> anyhow::Result<()> {
+        let vote_pubkeys = vote_accounts
+            .current
+            .iter()
+            .chain(vote_accounts.delinquent.iter())
+            .filter(|rpc| self.is_whitelisted(&rpc.vote_pubkey))
+            .map(|rpc| Pubkey::from_str(&rpc.vote_pubkey))
+            .collect::<Result<Vec<_>, _>>()?;
+        // Rent-exempt minimums by account data length.
+        let mut rent_exempt_minimums: HashMap<usize, u64> = HashMap::new();
+
+        for chunk in vote_pubkeys.chunks(chunk_size) {
+            let accounts = client.get_multiple_accounts(chunk)?;
+            for (pubkey, account) in chunk
+                .iter()
+                .zip(accounts)
+                .flat_map(|(p, oa)| oa.map(|a| (p, a)))
+            {
+                let minimum = match rent_exempt_minimums.get(&account.data.len()) {
+                    Some(minimum) => *minimum,
+                    None => {
+                        let minimum =
+                            client.get_minimum_balance_for_rent_exemption(account.data.len())?;
+                        rent_exempt_minimums.insert(account.data.len(), minimum);
+                        minimum
+                    }
+                };
+                let label = self.pubkey_labels.label(&pubkey.to_string());
+                self.vote_account_balance
+                    .get_metric_with_label_values(&[&label])
+                    .map(|m| m.set(account.lamports as i64))?;
+                self.vote_account_rent_exempt
+                    .get_metric_with_label_values(&[&label])
+                    .map(|m| m.set((account.lamports >= minimum) as i64))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Exports the vote credits earned by whitelisted validators in the current epoch, and their
+    /// percentile rank among the credits of all active validators.
+    pub fn export_epoch_credits(
+        &self,
+        vote_accounts: &RpcVoteAccountStatus,
+        epoch_info: &EpochInfo,
+    ) -> anyhow::Result<()> {
+        let mut active_credits: Vec<u64> = vote_accounts
+            .current
+            .iter()
+            .map(|v| current_epoch_credits(v, epoch_info))
+            .collect();
+        active_credits.sort_unstable();
+
+        for v in vote_accounts
+            .current
+            .iter()
+            .chain(vote_accounts.delinquent.iter())
+            .filter(|rpc| self.is_whitelisted(&rpc.vote_pubkey))
+        {
+            let credits = current_epoch_credits(v, epoch_info);
+            let label = self.pubkey_labels.label(&v.vote_pubkey);
+            self.epoch_credits
+                .get_metric_with_label_values(&[&label])
+                .map(|m| m.set(credits as i64))?;
+            if !active_credits.is_empty() {
+                let at_most = active_credits.partition_point(|c| *c <= credits);
+                self.credits_percentile
+                    .get_metric_with_label_values(&[&label])
+                    .map(|m| m.set(at_most as f64 / active_credits.len() as f64 * 100.))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Exports the activating and deactivating stake of whitelisted validators, summed over the stake
+    /// accounts delegated to them, and the number of those stake accounts. Fetches every stake
+    /// account of the cluster, which is expensive.
+    pub fn export_stake_states(
+        &self,
+        client: &RpcClient,
+        epoch_info: &EpochInfo,
+    ) -> anyhow::Result<()> {
+        let stake_history: StakeHistory =
+            bincode::deserialize(&client.get_account(&stake_history::id())?.data)
+                .context("could not deserialize stake history")?;
+
+        // Activating and deactivating stake and number of stake accounts by voter.
+        let mut stakes: HashMap<Pubkey, (u64, u64, u64)> = HashMap::new();
+        for (_, account) in client.get_program_accounts(&solana_stake_program::id())? {
+            let delegation = match bincode::deserialize::<StakeState>(&account.data)
+                .ok()
+                .and_then(|state| state.delegation())
+            {
+                Some(delegation) => delegation,
+                None => continue,
+            };
+            let (effective, activating, deactivating) = delegation
+                .stake_activating_and_deactivating(epoch_info.epoch, Some(&stake_history), true);
+            // Fully deactivated and not yet activating accounts are not counted.
+            if effective == 0 && activating == 0 && deactivating == 0 {
+                continue;
+            }
+            let voter_stakes = stakes.entry(delegation.voter_pubkey).or_default();
+            voter_stakes.0 += activating;
+            voter_stakes.1 += deactivating;
+            voter_stakes.2 += 1;
+        }
+
+        // Vote accounts left with only inactive stake accounts are removed.
+        self.activating_stake.reset();
+        self.deactivating_stake.reset();
+        self.stake_accounts.reset();
+        for (voter, (activating, deactivating, count)) in stakes {
+            let voter = voter.to_string();
+            if !self.is_whitelisted(&voter) {
+                continue;
+            }
+            let label = self.pubkey_labels.label(&voter);
+            self.activating_stake
+                .get_metric_with_label_values(&[&label])
+                .map(|m| m.set(activating as i64))?;
+            self.deactivating_stake
+                .get_metric_with_label_values(&[&label])
+                .map(|m| m.set(deactivating as i64))?;
+            self.stake_accounts
+                .get_metric_with_label_values(&[&label])
+                .map(|m| m.set(count as i64))?;
+        }
+
+        Ok(())
+    }
+
+    /// Exports the difference between the vote success rate, i.e., the vote credits per elapsed
+    /// slot of the current epoch, and the block production rate of whitelisted validators. Has to be
+    /// called after the skipped slots have been exported.
+    pub fn export_performance_discrepancy(
+        &self,
+        vote_accounts: &RpcVoteAccountStatus,
+        epoch_info: &EpochInfo,
+    ) -> anyhow::Result<()> {
+        // Validators without elapsed leader slots in the current epoch are removed.
+        self.performance_discrepancy.reset();
+        if epoch_info.slot_index == 0 {
+            return Ok(());
+        }
+        // Read the block counts without creating series for validators without leader slots. Like
+        // the vote credits, they only cover the current epoch.
+        let blocks_produced = pubkey_values(&self.blocks_produced);
+        let blocks_expected = pubkey_values(&self.blocks_expected);
+
+        for v in vote_accounts
+            .current
+            .iter()
+            .chain(vote_accounts.delinquent.iter())
+            .filter(|rpc| self.is_whitelisted(&rpc.vote_pubkey))
+        {
+            let node_label = self.pubkey_labels.label(&v.node_pubkey);
+            let (produced, expected) = match (
+                blocks_produced.get(&node_label),
+                blocks_expected.get(&node_label),
+            ) {
+                (Some(produced), Some(expected)) if *expected > 0.0 => (produced, expected),
+                _ => continue,
+            };
+            let vote_rate =
+                current_epoch_credits(v, epoch_info) as f64 / epoch_info.slot_index as f64;
+            let block_rate = produced / expected;
+            self.performance_discrepancy
+                .get_metric_with_label_values(&[&self.pubkey_labels.label(&v.vote_pubkey)])
+                .map(|m| m.set(vote_rate.min(1.0) - block_rate))?;
+        }
+
+        Ok(())
+    }
+
+    /// Exports the genesis hash of the cluster and the name of the cluster if it is a known one.
+    /// Since the genesis hash never changes, this is only done once.
+    pub fn export_cluster_info(&self, client: &RpcClient) -> anyhow::Result<()> {
+        let genesis_hash = client.get_genesis_hash()?.to_string();
+        let cluster = KNOWN_CLUSTERS
+            .iter()
+            .find(|(hash, _)| *hash == genesis_hash)
+            .map(|(_, name)| *name)
+            .unwrap_or("unknown");
+        self.cluster_info
+            .get_metric_with_label_values(&[&genesis_hash, cluster])
+            .map(|c| c.set(1))?;
+        Ok(())
+    }
+
+    /// Exports the health and the version of the RPC node. Failures are exported and logged rather
+    /// than returned, so that an unhealthy RPC node does not stop the update.
+    pub fn export_rpc_status(&self, client: &RpcClient) {
+        match self.count_rpc_error("get_health", client.get_health()) {
+            Ok(()) => self.rpc_healthy.set(1),
+            Err(e) => {
+                warn!("RPC node is not healthy: {}", e);
+                self.rpc_healthy.set(0);
+            }
+        }
+        match self.count_rpc_error("get_version", client.get_version()) {
+            Ok(version) => {
+                // The RPC node may have been upgraded since the last update.
+                self.rpc_version.reset();
+                self.rpc_version
+                    .with_label_values(&[&version.solana_core])
+                    .set(1);
+            }
+            Err(e) => warn!("Could not get RPC node version: {}", e),
+        }
+    }
+
+    /// Exports the total and circulating supply and the inflation rate of the cluster.
+    pub fn export_supply_info(&self, client: &RpcClient) -> anyhow::Result<()> {
+        let supply = client.supply()?.value;
+        self.total_supply_sol.set(lamports_to_sol(supply.total));
+        self.circulating_supply_sol
+            .set(lamports_to_sol(supply.circulating));
+        self.inflation_rate.set(client.get_inflation_rate()?.total);
+        Ok(())
+    }
+
     /// Exports gauges for epoch
     pub fn export_epoch_info(
         &self,
@@ -261,23 +1227,38 @@ impl PrometheusGauges {
 
         self.transaction_count
             .set(epoch_info.transaction_count.unwrap_or_default() as i64);
+        if let Some(transaction_count) = epoch_info.transaction_count {
+            let now = Instant::now();
+            let mut last = self.last_transaction_count.lock().unwrap();
+            // Not exported on the first update, or if the count went back, e.g., after the RPC node
+            // restarted from an older snapshot.
+            if let Some((last_count, last_time)) = *last {
+                let elapsed = now.duration_since(last_time).as_secs_f64();
+                if transaction_count >= last_count && elapsed > 0. {
+                    self.estimated_tps
+                        .set((transaction_count - last_count) as f64 / elapsed);
+                }
+            }
+            *last = Some((transaction_count, now));
+        }
         self.slot_height.set(epoch_info.absolute_slot as i64);
+        // Reset the loop count on epoch rollover, using the last exported epoch as the previous state.
+        if self.current_epoch.get() != epoch_info.epoch as i64 {
+            self.loops_since_epoch_change.set(0);
+        }
+        self.loops_since_epoch_change.inc();
         self.current_epoch.set(epoch_info.epoch as i64);
         self.current_epoch_first_slot.set(first_slot as i64);
         self.current_epoch_last_slot.set(last_slot as i64);
 
+        // Keep the previous average at the start of an epoch.
+        if epoch_info.slot_index < MIN_EXTRAPOLATION_SLOTS {
+            return Ok(());
+        }
         with_first_block(client, epoch_info.epoch, |block| {
             let average_slot_time = (OffsetDateTime::now_utc().unix_timestamp()
                 - client
-                    .get_block_with_config(
-                        block,
-                        RpcBlockConfig {
-                            encoding: Some(UiTransactionEncoding::Base64),
-                            transaction_details: Some(TransactionDetails::None),
-                            rewards: Some(false),
-                            commitment: None,
-                        },
-                    )?
+                    .get_block_with_config(block, block_config(client, false))?
                     .block_time
                     .unwrap()) as f64
                 / (epoch_info.slot_index) as f64;
@@ -288,38 +1269,171 @@ impl PrometheusGauges {
         Ok(())
     }
 
+    /// Observes the time per slot between evenly spaced blocks among the last `sample_blocks` blocks
+    /// of the current epoch. Blocks observed by a previous call are not observed again.
+    pub fn export_slot_times(
+        &self,
+        client: &RpcClient,
+        epoch_info: &EpochInfo,
+        sample_blocks: usize,
+    ) -> anyhow::Result<()> {
+        let first_slot = epoch_info.absolute_slot - epoch_info.slot_index;
+        let mut last_sample = self.last_slot_time_sample.lock().unwrap();
+        // Twice as many slots as blocks leaves room for skipped slots. The last sampled block is
+        // fetched again to observe the time until the next one.
+        let start_slot = epoch_info
+            .absolute_slot
+            .saturating_sub(2 * sample_blocks as u64)
+            .max(first_slot)
+            .max(last_sample.unwrap_or_default());
+        let blocks = client.get_blocks(start_slot, Some(epoch_info.absolute_slot))?;
+        let blocks = &blocks[blocks.len().saturating_sub(sample_blocks)..];
+        // Only the times of a few blocks spaced evenly back from the most recent one are fetched.
+        let step = (blocks.len().saturating_sub(1) + MAX_SLOT_TIME_SAMPLES - 2)
+            / (MAX_SLOT_TIME_SAMPLES - 1);
+        let mut sampled: Vec<u64> = blocks.iter().rev().step_by(step.max(1)).copied().collect();
+        sampled.reverse();
+
+        let mut block_times = Vec::with_capacity(sampled.len());
+        for block in sampled {
+            // Block times are not available for every block.
+            match client.get_block_time(block) {
+                Ok(time) => block_times.push((block, time)),
+                Err(e) => debug!("No block time for slot {}: {}", block, e),
+            }
+        }
+        for pair in block_times.windows(2) {
+            let ((slot, time), (next_slot, next_time)) = (pair[0], pair[1]);
+            // Block times are estimates and do not always increase.
+            if next_time <= time {
+                continue;
+            }
+            self.slot_time
+                .observe((next_time - time) as f64 / (next_slot - slot) as f64);
+        }
+        if let Some((slot, _)) = block_times.last() {
+            *last_sample = Some(*slot);
+        }
+
+        Ok(())
+    }
+
+    /// Exports whether each whitelisted node in `nodes` is `current_leader`, the leader of the
+    /// current slot.
+    pub fn export_current_leader(
+        &self,
+        nodes: &[RpcContactInfo],
+        node_whitelist: &Whitelist,
+        current_leader: Option<&str>,
+    ) -> anyhow::Result<()> {
+        for rpc in nodes
+            .iter()
+            .filter(|rpc| node_whitelist.contains(&rpc.pubkey))
+        {
+            self.is_current_leader
+                .get_metric_with_label_values(&[&self.pubkey_labels.label(&rpc.pubkey)])
+                .map(|m| m.set((current_leader == Some(rpc.pubkey.as_str())) as i64))?;
+        }
+
+        Ok(())
+    }
+
     /// Exports information about nodes
     pub fn export_nodes_info(
         &self,
         nodes: &[RpcContactInfo],
+        vote_accounts: &RpcVoteAccountStatus,
         client: &RpcClient,
         node_whitelist: &Whitelist,
+        identity_pubkeys: &Whitelist,
+        chunk_size: usize,
     ) -> anyhow::Result<()> {
-        // Balance of node pubkeys. Only exported if a whitelist is set!
+        // Balance of node pubkeys. Only exported for whitelisted nodes if a whitelist is set, and
+        // for the identity pubkeys regardless of whether they are in the cluster.
+        let mut balance_pubkeys: BTreeSet<&str> =
+            identity_pubkeys.0.iter().map(String::as_str).collect();
         if !node_whitelist.0.is_empty() {
-            let balances = nodes
-                .iter()
-                .filter(|rpc| node_whitelist.contains(&rpc.pubkey))
-                .map(|rpc| {
-                    Ok((
-                        rpc.pubkey.clone(),
-                        client.get_balance(&rpc.pubkey.parse()?)?,
-                    ))
-                })
-                .collect::<anyhow::Result<Vec<_>>>()?;
+            balance_pubkeys.extend(
+                nodes
+                    .iter()
+                    .filter(|rpc| node_whitelist.contains(&rpc.pubkey))
+                    .map(|rpc| rpc.pubkey.as_str()),
+            );
+        }
+        let balance_pubkeys = balance_pubkeys
+            .into_iter()
+            .map(Pubkey::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
 
-            for (pubkey, balance) in balances {
+        // Accounts that do not exist have no balance, as with `getBalance`.
+        for chunk in balance_pubkeys.chunks(chunk_size) {
+            let accounts = client.get_multiple_accounts(chunk)?;
+            for (pubkey, account) in chunk.iter().zip(accounts) {
+                let balance = account.map_or(0, |a| a.lamports);
+                let label = self.pubkey_labels.label(&pubkey.to_string());
                 self.node_pubkey_balances
-                    .get_metric_with_label_values(&[&pubkey])
+                    .get_metric_with_label_values(&[&label])
                     .map(|c| c.set(balance as i64))?;
+                self.identity_balance_sol
+                    .get_metric_with_label_values(&[&label])
+                    .map(|c| c.set(lamports_to_sol(balance)))?;
             }
         }
 
+        // Client diversity of the whole cluster, regardless of the whitelist
+        let node_stakes: HashMap<&str, u64> = vote_accounts
+            .current
+            .iter()
+            .chain(vote_accounts.delinquent.iter())
+            .fold(HashMap::new(), |mut map, v| {
+                *map.entry(v.node_pubkey.as_str()).or_insert(0) += v.activated_stake;
+                map
+            });
+        let mut clients: HashMap<&str, (i64, u64)> = HashMap::new();
+        for rpc in nodes {
+            let entry = clients
+                .entry(client_name(rpc.version.as_deref()))
+                .or_insert((0, 0));
+            entry.0 += 1;
+            entry.1 += node_stakes.get(rpc.pubkey.as_str()).copied().unwrap_or(0);
+        }
+
+        // Clients that are no longer run by any node must not keep their previous count.
+        self.cluster_clients.reset();
+        self.cluster_client_stake.reset();
+        for (client, (count, stake)) in clients {
+            self.cluster_clients
+                .get_metric_with_label_values(&[client])
+                .map(|c| c.set(count))?;
+            self.cluster_client_stake
+                .get_metric_with_label_values(&[client])
+                .map(|c| c.set(stake as i64))?;
+        }
+
+        self.cluster_nodes_total.set(nodes.len() as i64);
+
+        // Highest version on the cluster. Nodes with unknown or unparseable versions are ignored.
+        let max_version = nodes
+            .iter()
+            .filter_map(|rpc| rpc.version.as_deref())
+            .filter_map(|v| Version::parse(v).ok())
+            .max();
+
         let nodes = nodes
             .iter()
             .filter(|rpc| node_whitelist.contains(&rpc.pubkey))
             .collect::<Vec<_>>();
 
+        if let Some(max_version) = max_version {
+            for rpc in &nodes {
+                if let Some(version) = rpc.version.as_deref().and_then(|v| Version::parse(v).ok()) {
+                    self.version_behind
+                        .get_metric_with_label_values(&[&self.pubkey_labels.label(&rpc.pubkey)])
+                        .map(|c| c.set(minor_versions_behind(&version, &max_version) as i64))?;
+                }
+            }
+        }
+
         // Export number of nodes
         self.nodes.set(nodes.len() as i64);
 
@@ -345,14 +1459,10 @@ impl PrometheusGauges {
         nodes: &[RpcContactInfo],
         vote_accounts: &RpcVoteAccountStatus,
         cache: &GeolocationCache,
-        maxmind: &MaxMindAPIKey,
+        sources: GeolocationSources<'_>,
         node_whitelist: &Whitelist,
+        config: &ExporterConfig,
     ) -> anyhow::Result<()> {
-        // Define all types here
-        type RpcInfo = (RpcContactInfo, RpcVoteAccountInfo);
-        type RpcInfoMaybeGeo = (RpcContactInfo, RpcVoteAccountInfo, Option<CityApiResponse>);
-        type RpcInfoGeo = (RpcContactInfo, RpcVoteAccountInfo, CityApiResponse);
-
         // All nodes that are validators
         let validator_nodes = {
             // Mapping of pubkey -> vote account info.
@@ -375,22 +1485,293 @@ impl PrometheusGauges {
                 .collect::<Vec<RpcInfo>>()
         };
 
-        // If whitelist exists, remove all non-listed pubkeys
+        // If whitelist exists, remove all non-listed pubkeys. Nodes without a public IP address
+        // cannot be geolocated.
         let validator_nodes = validator_nodes
             .into_iter()
-            .filter(|(contact, _)| node_whitelist.contains(&contact.pubkey))
+            .filter(|(contact, _)| {
+                node_whitelist.contains(&contact.pubkey)
+                    && get_primary_contact_ip(contact).is_some()
+            })
             .collect::<Vec<_>>();
 
+        let geolocations = match sources.primary {
+            GeolocationBackend::Api(maxmind) => {
+                self.fetch_api_geolocations(validator_nodes, cache, maxmind, config)
+                    .await?
+            }
+            GeolocationBackend::Local(database) => validator_nodes
+                .into_iter()
+                .filter_map(|(contact, vote)| {
+                    let ip = get_primary_contact_ip(&contact)?;
+                    match database.lookup(ip) {
+                        Ok(city) => Some((contact, vote, city)),
+                        Err(e) => {
+                            error!("Could not look up {} in MaxMind database: {:?}", ip, e);
+                            None
+                        }
+                    }
+                })
+                .collect(),
+        };
+        let geolocations = match sources.fallback {
+            Some(fallback) => self.fill_missing_geolocations(geolocations, fallback),
+            None => geolocations,
+        };
+
+        // Gauges
+        let mut isp_staked: HashMap<String, u64> = HashMap::new();
+        let mut isp_count: HashMap<String, u64> = HashMap::new();
+        let mut dc_staked: HashMap<String, u64> = HashMap::new();
+        let mut region_staked: HashMap<String, u64> = HashMap::new();
+        let mut country_staked: HashMap<String, u64> = HashMap::new();
+        let mut continent_staked: HashMap<String, u64> = HashMap::new();
+
+        // Validators that moved must not keep the series of their previous location.
+        self.geo_info.reset();
+
+        for (_, validator, city) in &geolocations {
+            let isp = city
+                .traits
+                .as_ref()
+                .map(|t| t.isp.clone())
+                .unwrap_or_else(|| "unknown".to_string());
+
+            // solana_validator_geo_info
+            let country = city
+                .country
+                .as_ref()
+                .map(|c| c.iso_code.as_str())
+                .unwrap_or("unknown");
+            let city_name = city
+                .city
+                .as_ref()
+                .and_then(|c| c.names.get("en"))
+                .map(|name| name.as_str())
+                .unwrap_or("unknown");
+            let asn = city
+                .traits
+                .as_ref()
+                .map(|t| t.autonomous_system_number.to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+            self.geo_info
+                .get_metric_with_label_values(&[
+                    &self.pubkey_labels.label(&validator.vote_pubkey),
+                    country,
+                    city_name,
+                    &isp,
+                    &asn,
+                ])
+                .map(|c| c.set(1))?;
+
+            // solana_active_validators_isp_stake
+            let s = isp_staked.entry(isp.clone()).or_default();
+            *s += validator.activated_stake;
+
+            // solana_active_validators_isp_count
+            let c = isp_count.entry(isp).or_default();
+            *c += 1;
+
+            // solana_active_validators_dc_stake
+            let dc = dc_staked
+                .entry(DatacenterIdentifier::from(city.clone()).label(&config.datacenter_overrides))
+                .or_default();
+            *dc += validator.activated_stake;
+
+            // solana_stake_by_region
+            let region = config
+                .regions
+                .region(
+                    city.traits.as_ref().map(|t| t.autonomous_system_number),
+                    city.country.as_ref().map(|c| c.iso_code.as_str()),
+                )
+                .unwrap_or("unknown");
+            let r = region_staked.entry(region.to_string()).or_default();
+            *r += validator.activated_stake;
+
+            // solana_stake_by_country
+            let c = country_staked.entry(country.to_string()).or_default();
+            *c += validator.activated_stake;
+
+            // solana_stake_by_continent
+            let continent = city
+                .continent
+                .as_ref()
+                .map(|c| c.code.as_str())
+                .unwrap_or("unknown");
+            let c = continent_staked.entry(continent.to_string()).or_default();
+            *c += validator.activated_stake;
+        }
+
+        // Set gauges
+        for (isp, count) in &isp_count {
+            self.isp_count
+                .get_metric_with_label_values(&[isp])
+                .map(|c| c.set(*count as i64))?;
+        }
+
+        for (isp, staked) in &isp_staked {
+            self.isp_by_stake
+                .get_metric_with_label_values(&[isp])
+                .map(|c| c.set(*staked as i64))?;
+        }
+
+        for (identifier, staked) in &dc_staked {
+            self.dc_by_stake
+                .get_metric_with_label_values(&[identifier])
+                .map(|c| c.set(*staked as i64))?;
+        }
+
+        for (region, staked) in &region_staked {
+            self.stake_by_region
+                .get_metric_with_label_values(&[region])
+                .map(|c| c.set(*staked as i64))?;
+        }
+
+        for (country, staked) in &country_staked {
+            self.stake_by_country
+                .get_metric_with_label_values(&[country])
+                .map(|c| c.set(*staked as i64))?;
+        }
+
+        for (continent, staked) in &continent_staked {
+            self.stake_by_continent
+                .get_metric_with_label_values(&[continent])
+                .map(|c| c.set(*staked as i64))?;
+        }
+
+        self.last_geolocation_refresh
+            .set(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64);
+
+        Ok(())
+    }
+
+    /// Exports the location of the RPC node at `rpc_url`. Since the RPC node does not move while the
+    /// exporter is running, this is only done once.
+    pub async fn export_rpc_location(
+        &self,
+        rpc_url: &str,
+        cache: &GeolocationCache,
+        sources: GeolocationSources<'_>,
+        config: &ExporterConfig,
+    ) -> anyhow::Result<()> {
+        let url = reqwest::Url::parse(rpc_url)?;
+        let host = url
+            .host_str()
+            .ok_or_else(|| anyhow!("RPC URL has no host: {}", rpc_url))?;
+        let port = url.port_or_known_default().unwrap_or_default();
+        let ip = (host, port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("Could not resolve RPC host {}", host))?
+            .ip();
+        if !is_public(&ip) {
+            return Err(anyhow!("RPC node has a non-public address {}", ip));
+        }
+
+        let mut city = match sources.primary {
+            GeolocationBackend::Local(database) => database.lookup(ip)?,
+            GeolocationBackend::Api(maxmind) => {
+                let ttl = Duration::days(config.geo_cache_ttl_days.days(0));
+                let cached = cache.fetch_ip_address_with_invalidation(&ip, |date| {
+                    date + ttl < OffsetDateTime::now_utc().date()
+                })?;
+                match cached {
+                    Some(geo) => {
+                        self.geolocation_cache_hits.inc();
+                        geo.response
+                    }
+                    None => {
+                        let city = self.fetch_maxmind_city(ip, maxmind).await?;
+                        if is_cacheable(&city) {
+                            cache.add_ip_address(&ip, &city.clone().into())?;
+                        }
+                        city
+                    }
+                }
+            }
+        };
+
+        if let Some(fallback) = sources.fallback {
+            if is_incomplete(&city) && fallback.fill_missing(ip, &mut city)? {
+                self.geolocation_fallback.inc();
+            }
+        }
+
+        let region = config
+            .regions
+            .region(
+                city.traits.as_ref().map(|t| t.autonomous_system_number),
+                city.country.as_ref().map(|c| c.iso_code.as_str()),
+            )
+            .unwrap_or("unknown")
+            .to_string();
+        let datacenter = DatacenterIdentifier::from(city).label(&config.datacenter_overrides);
+        debug!("RPC node {} is at {} in {}", ip, datacenter, region);
+        self.rpc_location
+            .get_metric_with_label_values(&[&datacenter, &region])
+            .map(|c| c.set(1))?;
+        Ok(())
+    }
+
+    /// Fills in the missing fields of `geolocations` that lack a country or city from `fallback`.
+    fn fill_missing_geolocations(
+        &self,
+        geolocations: Vec<RpcInfoGeo>,
+        fallback: &LocalGeolocationDatabase,
+    ) -> Vec<RpcInfoGeo> {
+        geolocations
+            .into_iter()
+            .map(|(contact, vote, mut city)| {
+                let ip = get_primary_contact_ip(&contact).filter(|_| is_incomplete(&city));
+                if let Some(ip) = ip {
+                    match fallback.fill_missing(ip, &mut city) {
+                        Ok(true) => self.geolocation_fallback.inc(),
+                        Ok(false) => (),
+                        Err(e) => debug!("Could not look up {} in fallback database: {:?}", ip, e),
+                    }
+                }
+                (contact, vote, city)
+            })
+            .collect()
+    }
+
+    /// Requests the geolocation of `ip` from the MaxMind web API.
+    fn fetch_maxmind_city(
+        &self,
+        ip: IpAddr,
+        maxmind: &MaxMindAPIKey,
+    ) -> impl Future<Output = reqwest::Result<CityApiResponse>> {
+        self.maxmind_lookups.inc();
+        self.client
+            .get(format!("{}/{}", MAXMIND_CITY_URI, ip))
+            .basic_auth(maxmind.username(), Some(maxmind.password()))
+            .send()
+            // Error responses, such as for exhausted credit, are JSON objects as well.
+            .and_then(|resp| future::ready(resp.error_for_status()))
+            .and_then(|resp| resp.json::<CityApiResponse>())
+    }
+
+    /// Returns the geolocations of `validator_nodes`, fetching those that are not cached from the
+    /// MaxMind web API.
+    async fn fetch_api_geolocations(
+        &self,
+        validator_nodes: Vec<RpcInfo>,
+        cache: &GeolocationCache,
+        maxmind: &MaxMindAPIKey,
+        config: &ExporterConfig,
+    ) -> anyhow::Result<Vec<RpcInfoGeo>> {
         // Separate cached data from uncached data
         let (cached, uncached): (Vec<RpcInfoMaybeGeo>, Vec<RpcInfoMaybeGeo>) = validator_nodes
             .into_iter()
             .map(|(contact, vote)| {
+                let ttl = Duration::days(config.geo_cache_ttl_days.days(vote.activated_stake));
                 let cached = cache
                     .fetch_ip_address_with_invalidation(
-                        &get_rpc_contact_ip(&contact).with_context(|| {
+                        &get_primary_contact_ip(&contact).with_context(|| {
                             format!("Validator node has no IP: {:?} {:?}", contact, vote)
                         })?,
-                        |date| date + Duration::week() < OffsetDateTime::now_utc().date(),
+                        |date| date + ttl < OffsetDateTime::now_utc().date(),
                     )?
                     .map(|geo| geo.response);
                 Ok((contact, vote, cached))
@@ -398,6 +1779,7 @@ impl PrometheusGauges {
             .collect::<anyhow::Result<Vec<RpcInfoMaybeGeo>>>()?
             .into_iter()
             .partition(|(_, _, db)| db.is_some());
+        self.geolocation_cache_hits.inc_by(cached.len() as u64);
 
         let mut geolocations = cached
             .into_iter()
@@ -412,24 +1794,19 @@ impl PrometheusGauges {
             &geolocations.len()
         );
 
+        // At most `maxmind_max_concurrent` requests are in flight to stay below the rate limit.
         let (uncached_ok, uncached_err): (Vec<_>, Vec<_>) =
-            futures::future::join_all(uncached.into_iter().map(|(contact, vote, _)| {
+            futures::stream::iter(uncached.into_iter().map(|(contact, vote, _)| {
                 debug!(
                     "Contacting Maxmind for: {:?}",
-                    get_rpc_contact_ip(&contact).unwrap()
+                    get_primary_contact_ip(&contact).unwrap()
                 );
 
-                self.client
-                    .get(format!(
-                        "{}/{}",
-                        MAXMIND_CITY_URI,
-                        get_rpc_contact_ip(&contact).unwrap()
-                    ))
-                    .basic_auth(maxmind.username(), Some(maxmind.password()))
-                    .send()
-                    .and_then(|resp| resp.json::<CityApiResponse>())
-                    .and_then(|json: CityApiResponse| async { Ok((contact, vote, json)) })
+                self.fetch_maxmind_city(get_primary_contact_ip(&contact).unwrap(), maxmind)
+                    .map_ok(|json| (contact, vote, json))
             }))
+            .buffer_unordered(config.maxmind_max_concurrent)
+            .collect::<Vec<_>>()
             .await
             .into_iter()
             .collect::<Vec<reqwest::Result<RpcInfoGeo>>>()
@@ -451,60 +1828,135 @@ impl PrometheusGauges {
         }
 
         // Add API requested data into database
-        for (contact, _, city) in &uncached {
-            cache.add_ip_address(&get_rpc_contact_ip(contact).unwrap(), &city.clone().into())?;
-            debug!("Caching into DB {:?}", get_rpc_contact_ip(contact).unwrap());
+        for (contact, _, city) in uncached.iter().filter(|(_, _, city)| is_cacheable(city)) {
+            cache.add_ip_address(
+                &get_primary_contact_ip(contact).unwrap(),
+                &city.clone().into(),
+            )?;
+            debug!(
+                "Caching into DB {:?}",
+                get_primary_contact_ip(contact).unwrap()
+            );
         }
 
         // Add API requested data into collection
         geolocations.append(&mut uncached);
 
-        // Gauges
-        let mut isp_staked: HashMap<String, u64> = HashMap::new();
-        let mut isp_count: HashMap<String, u64> = HashMap::new();
-        let mut dc_staked: HashMap<DatacenterIdentifier, u64> = HashMap::new();
+        Ok(geolocations)
+    }
+}
 
-        for (_, validator, city) in &geolocations {
-            let isp = &city.traits.isp;
+impl Default for PrometheusGauges {
+    fn default() -> Self {
+        Self::new(
+            Whitelist::default(),
+            PubkeyLabels::default(),
+            None,
+            reqwest::Client::new(),
+            String::new(),
+            false,
+        )
+    }
+}
 
-            // solana_active_validators_isp_stake
-            let s = isp_staked.entry(isp.clone()).or_default();
-            *s += validator.activated_stake;
+/// Returns `true` iff `city` has a country or network traits. Responses without either carry no
+/// geolocation and are not cached, so that they are requested again.
+fn is_cacheable(city: &CityApiResponse) -> bool {
+    city.country.is_some() || city.traits.is_some()
+}
 
-            // solana_active_validators_isp_count
-            let c = isp_count.entry(isp.clone()).or_default();
-            *c += 1;
+/// Returns `true` iff `city` lacks a country or city.
+fn is_incomplete(city: &CityApiResponse) -> bool {
+    city.country.is_none() || city.city.is_none()
+}
 
-            // solana_active_validators_dc_stake
-            let dc = dc_staked.entry(city.clone().into()).or_default();
-            *dc += validator.activated_stake;
-        }
+/// Registers the standard process metrics, such as CPU time, resident memory and open file
+/// descriptors, of the exporter.
+#[cfg(target_os = "linux")]
+fn register_process_collector() {
+    let collector = ProcessCollector::for_self();
+    if let Err(e) = prometheus_exporter::prometheus::register(Box::new(collector)) {
+        warn!("Could not register process metrics: {}", e);
+    }
+}
 
-        // Set gauges
-        for (isp, count) in &isp_count {
-            self.isp_count
-                .get_metric_with_label_values(&[isp])
-                .map(|c| c.set(*count as i64))?;
-        }
+/// Process metrics are only collected on Linux.
+#[cfg(not(target_os = "linux"))]
+fn register_process_collector() {
+    warn!("Process metrics are only available on Linux");
+}
 
-        for (isp, staked) in &isp_staked {
-            self.isp_by_stake
-                .get_metric_with_label_values(&[isp])
-                .map(|c| c.set(*staked as i64))?;
-        }
+/// Returns the vote credits earned by `vote_account` in the current epoch.
+fn current_epoch_credits(vote_account: &RpcVoteAccountInfo, epoch_info: &EpochInfo) -> u64 {
+    // Entries are (epoch, credits, previous credits). A validator that has not voted in the current
+    // epoch yet has no entry for it.
+    vote_account
+        .epoch_credits
+        .iter()
+        .find(|(epoch, _, _)| *epoch == epoch_info.epoch)
+        .map(|(_, credits, prev_credits)| credits.saturating_sub(*prev_credits))
+        .unwrap_or(0)
+}
 
-        for (identifier, staked) in &dc_staked {
-            self.dc_by_stake
-                .get_metric_with_label_values(&[&identifier.to_string()])
-                .map(|c| c.set(*staked as i64))?;
-        }
+/// Returns how many minor versions `version` is behind `max_version`. A version with an older major
+/// version is one minor version further behind than the oldest minor version of `max_version`.
+fn minor_versions_behind(version: &Version, max_version: &Version) -> u64 {
+    if version.major < max_version.major {
+        max_version.minor + 1
+    } else {
+        max_version.minor.saturating_sub(version.minor)
+    }
+}
 
-        Ok(())
+/// Returns the minimum number of stakes in `stakes` whose sum exceeds a third of the total, which is
+/// what it takes to halt the cluster.
+fn nakamoto_coefficient(mut stakes: Vec<u64>) -> usize {
+    let total: u128 = stakes.iter().map(|s| *s as u128).sum();
+    stakes.sort_unstable_by(|a, b| b.cmp(a));
+    let mut sum = 0u128;
+    for (i, stake) in stakes.into_iter().enumerate() {
+        sum += stake as u128;
+        if sum * 3 > total {
+            return i + 1;
+        }
     }
+    0
 }
 
-impl Default for PrometheusGauges {
-    fn default() -> Self {
-        Self::new(Whitelist::default())
+/// Returns the Gini coefficient of `stakes`, from 0 for equal stakes to almost 1 if a single
+/// validator has all the stake.
+fn gini_coefficient(mut stakes: Vec<u64>) -> f64 {
+    let total: f64 = stakes.iter().map(|s| *s as f64).sum();
+    if stakes.is_empty() || total == 0.0 {
+        return 0.0;
     }
+    stakes.sort_unstable();
+    let n = stakes.len() as f64;
+    let weighted: f64 = stakes
+        .iter()
+        .enumerate()
+        .map(|(i, stake)| (i + 1) as f64 * *stake as f64)
+        .sum();
+    2.0 * weighted / (n * total) - (n + 1.0) / n
+}
+
+/// Returns the values of the metrics of `collector` by their `pubkey` label.
+pub fn pubkey_values(collector: &impl Collector) -> BTreeMap<String, f64> {
+    collector
+        .collect()
+        .iter()
+        .flat_map(|family| family.get_metric())
+        .filter_map(|metric| {
+            metric
+                .get_label()
+                .iter()
+                .find(|label| label.get_name() == PUBKEY_LABEL)
+                .map(|label| {
+                    (
+                        label.get_value().to_string(),
+                        metric.get_gauge().get_value(),
+                    )
+                })
+        })
+        .collect()
 }
@@ -1,7 +1,23 @@
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 pub const MAXMIND_CITY_URI: &str = "https://geoip.maxmind.com/geoip/v2.1/city";
 
+/// Builds the HTTP client for MaxMind API requests, which go through `http_proxy` if set. Both
+/// connecting and the whole request time out after `timeout`.
+pub fn maxmind_client(
+    http_proxy: Option<&str>,
+    timeout: Duration,
+) -> reqwest::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(timeout)
+        .timeout(timeout);
+    if let Some(http_proxy) = http_proxy {
+        builder = builder.proxy(reqwest::Proxy::all(http_proxy)?);
+    }
+    builder.build()
+}
+
 /// An API key that can be used to access MaxMind services.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MaxMindAPIKey {
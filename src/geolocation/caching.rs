@@ -1,5 +1,6 @@
 use anyhow::Context;
 use geoip2_city::CityApiResponse;
+use log::warn;
 use serde::{Deserialize, Serialize};
 use std::net::IpAddr;
 use time::{Date, OffsetDateTime};
@@ -45,12 +46,25 @@ impl GeolocationCache {
 
     /// Fetches the cached information about an IP address, after checking if will be invalidated.
     /// `f` is a function that will return `true` if, given a date, the cached data should be considered stale.
-    pub fn fetch_ip_address_with_invalidation(
+    pub fn fetch_ip_address_with_invalidation<F>(
         &self,
         ip_address: &IpAddr,
-        f: fn(Date) -> bool,
-    ) -> anyhow::Result<Option<GeoInfo>> {
-        match self.fetch_ip_address(ip_address)? {
+        f: F,
+    ) -> anyhow::Result<Option<GeoInfo>>
+    where
+        F: Fn(Date) -> bool,
+    {
+        let fetched = match self.fetch_ip_address(ip_address) {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                // Entries cached by older versions may not deserialize. Treat them as stale.
+                warn!("Discarding cached info for {}: {:?}", ip_address, e);
+                self.remove_ip_address_raw(ip_address)?;
+                None
+            }
+        };
+
+        match fetched {
             // Database has it cached...
             Some(g) => {
                 if f(g.fetched_at) {
@@ -68,6 +82,14 @@ impl GeolocationCache {
         }
     }
 
+    /// Removes cached information about an IP address without deserializing it.
+    fn remove_ip_address_raw(&self, ip_address: &IpAddr) -> anyhow::Result<()> {
+        self.tree
+            .remove(bincode::serialize(ip_address)?)
+            .context("could not remove IP address")?;
+        Ok(())
+    }
+
     /// Removes cached information about an IP address.
     pub fn remove_ip_address(&self, ip_address: &IpAddr) -> anyhow::Result<Option<GeoInfo>> {
         self.tree
@@ -1,12 +1,14 @@
 use geoip2_city::CityApiResponse;
+use std::collections::HashMap;
 use std::fmt;
 use std::fmt::{Display, Formatter};
 
 /// A unique identifier for a datacenter. It consists of a AS Number, ISO-3166 country code, and
-/// optionally a city name.
+/// optionally a city name, or the AS organization if the city is unknown.
 #[derive(Clone, Debug, Hash, Ord, PartialOrd, Eq, PartialEq)]
 pub struct DatacenterIdentifier {
     autonomous_system_number: u32,
+    autonomous_system_organization: Option<String>,
     country_code: String,
     city_name: Option<String>,
 }
@@ -14,7 +16,15 @@ pub struct DatacenterIdentifier {
 impl From<CityApiResponse> for DatacenterIdentifier {
     fn from(val: CityApiResponse) -> Self {
         Self {
-            autonomous_system_number: val.traits.autonomous_system_number,
+            autonomous_system_number: val
+                .traits
+                .as_ref()
+                .map(|t| t.autonomous_system_number)
+                .unwrap_or_default(),
+            autonomous_system_organization: val
+                .traits
+                .map(|t| t.autonomous_system_organization)
+                .filter(|organization| !organization.is_empty()),
             country_code: val
                 .country
                 .map(|c| c.iso_code)
@@ -24,14 +34,29 @@ impl From<CityApiResponse> for DatacenterIdentifier {
     }
 }
 
+impl DatacenterIdentifier {
+    /// Returns the label of the datacenter, which is the name its AS number maps to in `overrides`
+    /// if there is one, and the identifier otherwise.
+    pub fn label(&self, overrides: &HashMap<String, String>) -> String {
+        overrides
+            .get(&self.autonomous_system_number.to_string())
+            .cloned()
+            .unwrap_or_else(|| self.to_string())
+    }
+}
+
 impl Display for DatacenterIdentifier {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        match &self.city_name {
-            Some(cn) => {
+        match self
+            .city_name
+            .as_ref()
+            .or_else(|| self.autonomous_system_organization.as_ref())
+        {
+            Some(name) => {
                 write!(
                     f,
                     "{}-{}-{}",
-                    self.autonomous_system_number, self.country_code, cn
+                    self.autonomous_system_number, self.country_code, name
                 )
             }
             None => {
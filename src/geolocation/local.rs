@@ -0,0 +1,148 @@
+use anyhow::Context;
+use geoip2_city::{
+    City, CityApiResponse, Continent, Country, Location, Postal, RepresentedCountry, Subdivisions,
+    Traits,
+};
+use maxminddb::{geoip2, Reader};
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Local MaxMind databases in the `.mmdb` format, used instead of the web API.
+pub struct LocalGeolocationDatabase {
+    /// A GeoLite2 or GeoIP2 City database.
+    city: Reader<Vec<u8>>,
+    /// An optional GeoLite2 ASN database providing the autonomous system of IP addresses.
+    asn: Option<Reader<Vec<u8>>>,
+}
+
+impl LocalGeolocationDatabase {
+    /// Opens the City database at `city_path` and optionally the ASN database at `asn_path`.
+    pub fn open(city_path: &Path, asn_path: Option<&Path>) -> anyhow::Result<Self> {
+        let city = Reader::open_readfile(city_path)
+            .with_context(|| format!("Could not open MaxMind database {:?}", city_path))?;
+        let asn = asn_path
+            .map(|path| {
+                Reader::open_readfile(path)
+                    .with_context(|| format!("Could not open MaxMind database {:?}", path))
+            })
+            .transpose()?;
+        Ok(Self { city, asn })
+    }
+
+    /// Looks up `ip` and returns the same data as the GeoIP2 City web API would. Since the
+    /// GeoLite2 databases do not provide ISPs, the ISP is the autonomous system organization.
+    pub fn lookup(&self, ip: IpAddr) -> anyhow::Result<CityApiResponse> {
+        let city: geoip2::City = self.city.lookup(ip)?;
+        let traits = match &self.asn {
+            Some(asn) => {
+                let asn: geoip2::Asn = asn.lookup(ip)?;
+                let organization = asn.autonomous_system_organization.unwrap_or_default();
+                Some(Traits {
+                    autonomous_system_number: asn.autonomous_system_number.unwrap_or_default(),
+                    autonomous_system_organization: organization.to_string(),
+                    domain: None,
+                    ip_address: ip,
+                    isp: organization.to_string(),
+                    network: format!("{}/{}", ip, if ip.is_ipv4() { 32 } else { 128 }),
+                    organization: organization.to_string(),
+                })
+            }
+            None => None,
+        };
+
+        Ok(CityApiResponse {
+            city: city.city.map(|c| City {
+                geoname_id: c.geoname_id.unwrap_or_default(),
+                names: names(c.names),
+            }),
+            continent: city.continent.map(|c| Continent {
+                code: c.code.unwrap_or_default().to_string(),
+                geoname_id: c.geoname_id.unwrap_or_default(),
+                names: names(c.names),
+            }),
+            country: city.country.map(country),
+            location: city.location.map(|l| Location {
+                accuracy_radius: l.accuracy_radius.unwrap_or_default().into(),
+                latitude: l.latitude.unwrap_or_default() as f32,
+                longitude: l.longitude.unwrap_or_default() as f32,
+                metro_code: l.metro_code.map(Into::into),
+                time_zone: l.time_zone.unwrap_or_default().to_string(),
+            }),
+            postal: city.postal.map(|p| Postal {
+                code: p.code.unwrap_or_default().to_string(),
+            }),
+            registered_country: city.registered_country.map(country),
+            represented_country: city.represented_country.map(|c| RepresentedCountry {
+                geoname_id: c.geoname_id.unwrap_or_default(),
+                is_in_european_union: c.is_in_european_union.unwrap_or_default(),
+                iso_code: c.iso_code.unwrap_or_default().to_string(),
+                names: names(c.names),
+                repr_type: c.representation_type.unwrap_or_default().to_string(),
+            }),
+            subdivisions: city.subdivisions.map(|subdivisions| {
+                subdivisions
+                    .into_iter()
+                    .map(|s| Subdivisions {
+                        geoname_id: s.geoname_id.unwrap_or_default(),
+                        iso_code: s.iso_code.unwrap_or_default().to_string(),
+                        names: names(s.names),
+                    })
+                    .collect()
+            }),
+            traits,
+        })
+    }
+
+    /// Fills in the fields of `city` that are missing with the data of `ip` in this database.
+    /// Returns `true` iff any field was filled in.
+    pub fn fill_missing(&self, ip: IpAddr, city: &mut CityApiResponse) -> anyhow::Result<bool> {
+        let fallback = self.lookup(ip)?;
+        let mut filled = false;
+        fill(&mut city.city, fallback.city, &mut filled);
+        fill(&mut city.continent, fallback.continent, &mut filled);
+        fill(&mut city.country, fallback.country, &mut filled);
+        fill(&mut city.location, fallback.location, &mut filled);
+        fill(&mut city.postal, fallback.postal, &mut filled);
+        fill(
+            &mut city.registered_country,
+            fallback.registered_country,
+            &mut filled,
+        );
+        fill(
+            &mut city.represented_country,
+            fallback.represented_country,
+            &mut filled,
+        );
+        fill(&mut city.subdivisions, fallback.subdivisions, &mut filled);
+        fill(&mut city.traits, fallback.traits, &mut filled);
+        Ok(filled)
+    }
+}
+
+/// Sets `field` to `value` if `field` is `None` and `value` is not, recording that in `filled`.
+fn fill<T>(field: &mut Option<T>, value: Option<T>, filled: &mut bool) {
+    if field.is_none() && value.is_some() {
+        *field = value;
+        *filled = true;
+    }
+}
+
+/// Converts a country of the database into a country of the web API.
+fn country(c: geoip2::model::Country) -> Country {
+    Country {
+        geoname_id: c.geoname_id.unwrap_or_default(),
+        is_in_european_union: c.is_in_european_union.unwrap_or_default(),
+        iso_code: c.iso_code.unwrap_or_default().to_string(),
+        names: names(c.names),
+    }
+}
+
+/// Converts localized names of the database into localized names of the web API.
+fn names(names: Option<BTreeMap<&str, &str>>) -> HashMap<String, String> {
+    names
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(locale, name)| (locale.to_string(), name.to_string()))
+        .collect()
+}
@@ -1,12 +1,80 @@
+use crate::geolocation::api::MaxMindAPIKey;
+use crate::geolocation::local::LocalGeolocationDatabase;
 use solana_client::rpc_response::RpcContactInfo;
 use std::net::IpAddr;
 
 pub mod api;
 pub mod caching;
 pub mod identifier;
+pub mod local;
 
-/// Gets an IP address from a node. All three parameters of the node will be tried in order of TPU, Gossip,
-/// and then RPC.
-pub fn get_rpc_contact_ip(rpc: &RpcContactInfo) -> Option<IpAddr> {
-    rpc.tpu.or(rpc.gossip).or(rpc.rpc).map(|s| s.ip())
+/// Gets the distinct IP addresses of a node in order of Gossip, TPU, and then RPC.
+pub fn get_all_contact_ips(rpc: &RpcContactInfo) -> Vec<IpAddr> {
+    let mut ips = vec![];
+    for ip in [rpc.gossip, rpc.tpu, rpc.rpc]
+        .iter()
+        .flatten()
+        .map(|s| s.ip())
+    {
+        if !ips.contains(&ip) {
+            ips.push(ip);
+        }
+    }
+    ips
+}
+
+/// Gets the primary public IP address of a node, which is the first public one of its Gossip, TPU
+/// and RPC addresses. The Gossip address is preferred since it is the one a datacenter is most
+/// reliably attributed by.
+pub fn get_primary_contact_ip(rpc: &RpcContactInfo) -> Option<IpAddr> {
+    get_all_contact_ips(rpc).into_iter().find(is_public)
+}
+
+/// Returns `true` iff `ip` is globally routable, i.e., worth geolocating.
+pub fn is_public(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            // 100.64.0.0/10 is shared address space for carrier-grade NAT.
+            let shared = a == 100 && b & 0b1100_0000 == 64;
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || shared)
+        }
+        IpAddr::V6(ip) => {
+            let first_segment = ip.segments()[0];
+            // fc00::/7 are unique local and fe80::/10 are link-local addresses.
+            let unique_local = first_segment & 0xfe00 == 0xfc00;
+            let link_local = first_segment & 0xffc0 == 0xfe80;
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || unique_local
+                || link_local)
+        }
+    }
+}
+
+/// Source of geolocation data.
+#[derive(Clone, Copy)]
+pub enum GeolocationBackend<'a> {
+    /// MaxMind's web API. Responses are cached in the persistent database.
+    Api(&'a MaxMindAPIKey),
+    /// Local MaxMind databases. Lookups are not cached.
+    Local(&'a LocalGeolocationDatabase),
+}
+
+/// Sources of geolocation data.
+#[derive(Clone, Copy)]
+pub struct GeolocationSources<'a> {
+    /// The source consulted for every IP address.
+    pub primary: GeolocationBackend<'a>,
+    /// A local database consulted only for IP addresses that the primary source could not locate
+    /// to a country and city.
+    pub fallback: Option<&'a LocalGeolocationDatabase>,
 }
@@ -0,0 +1,63 @@
+//! Liveness endpoint reporting whether the main loop keeps completing.
+
+use anyhow::anyhow;
+use log::{debug, error};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use time::OffsetDateTime;
+use tiny_http::{Response, Server};
+
+/// Path of the liveness endpoint.
+pub const HEALTH_PATH: &str = "/healthz";
+
+/// Unix timestamp of the last successful iteration of the main loop, shared with the health server.
+#[derive(Clone, Debug, Default)]
+pub struct LastSuccess(Arc<AtomicI64>);
+
+impl LastSuccess {
+    /// Records that an iteration of the main loop succeeded now.
+    pub fn update(&self) {
+        self.0.store(
+            OffsetDateTime::now_utc().unix_timestamp(),
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Returns `true` iff the last successful iteration was at most `max_age_seconds` ago.
+    pub fn is_recent(&self, max_age_seconds: u64) -> bool {
+        OffsetDateTime::now_utc().unix_timestamp() - self.0.load(Ordering::Relaxed)
+            <= max_age_seconds as i64
+    }
+}
+
+/// Starts serving `HEALTH_PATH` on `target` in a separate thread. The endpoint returns 200 if the
+/// main loop succeeded within the last `max_age_seconds` and 503 otherwise.
+pub fn start(
+    target: SocketAddr,
+    last_success: LastSuccess,
+    max_age_seconds: u64,
+) -> anyhow::Result<()> {
+    let server = Server::http(target)
+        .map_err(|e| anyhow!("Could not bind the health endpoint to {}: {}", target, e))?;
+    debug!("Serving {} on {}", HEALTH_PATH, target);
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            // The query string, if any, does not affect the response.
+            let response = if request.url().split('?').next() != Some(HEALTH_PATH) {
+                Response::from_string("not found").with_status_code(404)
+            } else if last_success.is_recent(max_age_seconds) {
+                Response::from_string("ok").with_status_code(200)
+            } else {
+                Response::from_string("stale").with_status_code(503)
+            };
+            if let Err(e) = request.respond(response) {
+                error!("Could not respond to health check: {:?}", e);
+            }
+        }
+    });
+
+    Ok(())
+}
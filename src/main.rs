@@ -12,75 +12,202 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::config::{ExporterConfig, Whitelist, CONFIG_FILE_NAME};
-use crate::gauges::PrometheusGauges;
-use crate::geolocation::api::MaxMindAPIKey;
+use crate::config::{
+    Commitment, ExporterConfig, GeoCacheTtl, Whitelist, WhitelistFile, CONFIG_FILE_NAME,
+    DEFAULT_ACCOUNT_FETCH_CHUNK_SIZE, DEFAULT_APY_LOOKBACK_EPOCHS, DEFAULT_HEALTH_MAX_AGE_SECONDS,
+    DEFAULT_MAXMIND_MAX_CONCURRENT, DEFAULT_MAXMIND_TIMEOUT_SECONDS, DEFAULT_RPC_MAX_RETRIES,
+    DEFAULT_RPC_TIMEOUT_SECONDS, DEFAULT_SCRAPE_INTERVAL_SECONDS,
+};
+use crate::gauges::{PrometheusGauges, PubkeyLabels};
+use crate::geolocation::api::{maxmind_client, MaxMindAPIKey};
 use crate::geolocation::caching::{GeolocationCache, GEO_DB_CACHE_TREE_NAME};
+use crate::geolocation::local::LocalGeolocationDatabase;
+use crate::geolocation::{GeolocationBackend, GeolocationSources};
+use crate::health::LastSuccess;
 use crate::persistent_database::{PersistentDatabase, DATABASE_FILE_NAME};
 use crate::rewards::caching::{
-    RewardsCache, APY_TREE_NAME, EPOCH_LENGTH_TREE_NAME, EPOCH_REWARDS_TREE_NAME,
-    EPOCH_VOTER_APY_TREE_NAME,
+    RewardsCache, APY_TREE_NAME, EPOCH_DELEGATOR_COUNT_TREE_NAME, EPOCH_LENGTH_TREE_NAME,
+    EPOCH_REWARDS_TREE_NAME, EPOCH_VOTER_APY_TREE_NAME,
 };
 use crate::rewards::RewardsMonitor;
+use crate::rpc_extra::retry_with_backoff;
+use crate::server::MetricsServer;
+use crate::slots::caching::{
+    SkippedSlotsCache, EPOCH_SLOT_COUNTS_TREE_NAME, LEADER_SCHEDULE_TREE_NAME,
+    LEADER_SLOT_COUNTS_TREE_NAME, RECENT_SLOTS_TREE_NAME, SKIPPED_SLOTS_TREE_NAME,
+};
 use crate::slots::SkippedSlotsMonitor;
-use anyhow::Context;
+use crate::vote_accounts::caching::{
+    VoteAccountsCache, VOTE_ACCOUNT_AUTHORITIES_TREE_NAME, VOTE_ACCOUNT_COMMISSIONS_TREE_NAME,
+    VOTE_ACCOUNT_IDENTITIES_TREE_NAME,
+};
+use crate::vote_accounts::VoteAccountsMonitor;
+use crate::webhook::caching::{WebhookCache, WEBHOOK_TREE_NAME};
+use crate::webhook::{EpochSummary, Webhook};
+use anyhow::{anyhow, Context};
 use clap::{load_yaml, App};
-use log::{debug, warn};
+use env_logger::Env;
+use log::{debug, error, info, warn};
+use prometheus_exporter::prometheus::{self, Encoder, TextEncoder};
 use solana_client::rpc_client::RpcClient;
+use solana_sdk::clock::Epoch;
 use std::fs::{create_dir_all, File};
-use std::io::Write;
+use std::io::{self, Write};
 use std::net::SocketAddr;
-use std::path::Path;
-use std::{fs, time::Duration};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, UNIX_EPOCH};
+use std::{env, fs, time::Duration};
+use tokio::runtime::Handle;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tokio::task;
 
 pub mod config;
 pub mod gauges;
 pub mod geolocation;
+pub mod health;
 pub mod persistent_database;
 pub mod rewards;
 pub mod rpc_extra;
+pub mod server;
+pub mod shadow;
 pub mod slots;
+pub mod vote_accounts;
+pub mod webhook;
 
 /// Name of directory where solana-exporter will store information
 pub const EXPORTER_DATA_DIR: &str = ".solana-exporter";
+/// Name of the solana-exporter directories under the XDG base directories.
+pub const XDG_DIR_NAME: &str = "solana-exporter";
+/// Config directory used if the home directory cannot be determined, e.g., in containers.
+pub const SYSTEM_CONFIG_DIR: &str = "/etc/solana-exporter";
+/// Data directory used if the home directory cannot be determined, e.g., in containers.
+pub const SYSTEM_DATA_DIR: &str = "/var/lib/solana-exporter";
 /// Current version of `solana-exporter`
 pub const SOLANA_EXPORTER_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Environment variable overriding the RPC address of the config file.
+pub const RPC_ENV_VAR: &str = "SOLANA_EXPORTER_RPC";
+/// Environment variable overriding the Prometheus target address of the config file.
+pub const TARGET_ENV_VAR: &str = "SOLANA_EXPORTER_TARGET";
+/// Environment variable overriding the MaxMind API username of the config file.
+pub const MAXMIND_USERNAME_ENV_VAR: &str = "MAXMIND_USERNAME";
+/// Environment variable overriding the MaxMind API password of the config file.
+pub const MAXMIND_PASSWORD_ENV_VAR: &str = "MAXMIND_PASSWORD";
+/// Comment at the top of generated config files.
+const GENERATED_CONFIG_HEADER: &str = "\
+# Geolocation through the MaxMind API needs the username and password of an API key. Set them in the
+# MAXMIND_USERNAME and MAXMIND_PASSWORD environment variables rather than in this file. Alternatively,
+# add a [maxmind] table with `username` and `password`, and optionally read the password from
+# `maxmind_password_file`.
+
+";
+/// Environment variable selecting the log format, `text` or `json`, unless set by `--log-format`.
+pub const LOG_FORMAT_ENV_VAR: &str = "SOLANA_EXPORTER_LOG_FORMAT";
+/// Log filter used unless overridden by `RUST_LOG`. Keeps the dependencies, in particular `sled`,
+/// from flooding the log.
+const DEFAULT_LOG_FILTER: &str = "warn,sled=warn,solana_exporter=info";
+/// Minimum interval between updates of the stake states, which fetch all stake accounts.
+const STAKE_STATES_INTERVAL: Duration = Duration::from_secs(600);
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    env_logger::init();
+    let start_time = Instant::now();
     // Read from CLI arguments
     let yaml = load_yaml!("cli.yml");
     let cli_configs = App::from_yaml(yaml).get_matches();
 
+    let log_format = cli_configs
+        .value_of("log-format")
+        .map(str::to_string)
+        .or_else(|| env::var(LOG_FORMAT_ENV_VAR).ok());
+    let mut logger =
+        env_logger::Builder::from_env(Env::default().default_filter_or(DEFAULT_LOG_FILTER));
+    match log_format.as_deref() {
+        None | Some("text") => {}
+        Some("json") => {
+            logger.format(|buf, record| {
+                let line = serde_json::json!({
+                    "timestamp": buf.timestamp().to_string(),
+                    "level": record.level().to_string(),
+                    "target": record.target(),
+                    "message": record.args().to_string(),
+                });
+                writeln!(buf, "{}", line)
+            });
+        }
+        Some(other) => return Err(anyhow!("Unknown log format {}", other)),
+    }
+    logger.init();
+
     // Subcommands
     match cli_configs.subcommand() {
         ("generate", Some(sc)) => {
             let template_config = ExporterConfig {
                 rpc: "http://localhost:8899".to_string(),
+                commitment: Commitment::default(),
                 target: SocketAddr::new("0.0.0.0".parse()?, 9179),
-                maxmind: Some(MaxMindAPIKey::new("username", "password")),
+                tls_cert_path: None,
+                tls_key_path: None,
+                metrics_username: None,
+                metrics_password: None,
+                scrape_interval_seconds: DEFAULT_SCRAPE_INTERVAL_SECONDS,
+                enable_geolocation: true,
+                geolocation_interval_seconds: None,
+                health_target: None,
+                health_max_age_seconds: DEFAULT_HEALTH_MAX_AGE_SECONDS,
+                rpc_max_retries: DEFAULT_RPC_MAX_RETRIES,
+                rpc_timeout_seconds: DEFAULT_RPC_TIMEOUT_SECONDS,
+                account_fetch_chunk_size: DEFAULT_ACCOUNT_FETCH_CHUNK_SIZE,
+                maxmind_db_path: None,
+                maxmind_asn_db_path: None,
+                geolocation_fallback_db_path: None,
+                maxmind_timeout_seconds: DEFAULT_MAXMIND_TIMEOUT_SECONDS,
+                maxmind_max_concurrent: DEFAULT_MAXMIND_MAX_CONCURRENT,
+                maxmind_password_file: None,
+                http_proxy: None,
+                export_rpc_location: false,
+                maxmind: None,
+                identity_pubkeys: None,
                 vote_account_whitelist: Some(Whitelist::default()),
+                pubkey_whitelist_file: None,
                 staking_account_whitelist: Some(Whitelist::default()),
+                geo_cache_ttl_days: GeoCacheTtl::default(),
+                rewards_retention_epochs: None,
+                geolocation_db_path: None,
+                rewards_db_path: None,
+                apy_lookback_epochs: DEFAULT_APY_LOOKBACK_EPOCHS,
+                skip_rate_window_slots: None,
+                slot_time_sample_blocks: None,
+                real_staking_apy: false,
+                track_supply: false,
+                export_vote_account_balances: false,
+                track_stake_states: false,
+                track_vote_authorities: false,
+                track_process_metrics: false,
+                metric_prefix: String::new(),
+                pubkey_label_salt: None,
+                shadow_metrics: Default::default(),
+                regions: Default::default(),
+                datacenter_overrides: Default::default(),
+                rank_filter: None,
+                webhook: None,
             };
 
             let location = sc
                 .value_of("output")
                 .map(|s| Path::new(s).to_path_buf())
                 .unwrap_or_else(|| {
-                    dirs::home_dir()
-                        .unwrap()
-                        .join(EXPORTER_DATA_DIR)
-                        .join(CONFIG_FILE_NAME)
+                    default_dir("XDG_CONFIG_HOME", SYSTEM_CONFIG_DIR).join(CONFIG_FILE_NAME)
                 });
 
-            // Only attempt to create .solana-exporter, if user specified location then don't try
-            // to create directories
+            // Only attempt to create the default directory, if user specified location then don't
+            // try to create directories
             if sc.value_of("output").is_none() {
                 create_dir_all(&location.parent().unwrap())?;
             }
 
             let mut file = File::create(location)?;
+            file.write_all(GENERATED_CONFIG_HEADER.as_bytes())?;
             file.write_all(toml::to_string_pretty(&template_config)?.as_ref())?;
             std::process::exit(0);
         }
@@ -88,111 +215,685 @@ async fn main() -> anyhow::Result<()> {
         (_, _) => {}
     }
 
-    let persistent_database = {
-        // Use override from CLI or default.
-        let location = cli_configs
-            .value_of("database")
-            .map(|s| Path::new(s).to_path_buf())
-            .unwrap_or_else(|| {
-                dirs::home_dir()
-                    .unwrap()
-                    .join(EXPORTER_DATA_DIR)
-                    .join(DATABASE_FILE_NAME)
-            });
-
-        // Show warning if database not found, since sled will make a new file?
-        if !location.exists() {
-            warn!("Database could not found at specified location. A new one will be generated!")
-        }
+    // Use override from CLI or default.
+    let database_location = cli_configs
+        .value_of("database")
+        .map(|s| Path::new(s).to_path_buf())
+        .unwrap_or_else(|| default_dir("XDG_DATA_HOME", SYSTEM_DATA_DIR).join(DATABASE_FILE_NAME));
 
-        PersistentDatabase::new(&location)
-    }?;
+    // Pruning only needs the database, not the config.
+    if let Some(sc) = cli_configs.subcommand_matches("prune") {
+        let before_epoch: Epoch = sc
+            .value_of("before-epoch")
+            .unwrap()
+            .parse()
+            .context("Invalid epoch")?;
+        return prune_database(&database_location, before_epoch);
+    }
 
-    let config = {
+    let (config, config_mtime) = {
         // Use override from CLI or default.
         let location = cli_configs
             .value_of("config")
             .map(|s| Path::new(s).to_path_buf())
             .unwrap_or_else(|| {
-                dirs::home_dir()
-                    .unwrap()
-                    .join(EXPORTER_DATA_DIR)
-                    .join(CONFIG_FILE_NAME)
+                default_dir("XDG_CONFIG_HOME", SYSTEM_CONFIG_DIR).join(CONFIG_FILE_NAME)
             });
 
-        let file_contents = fs::read_to_string(location).context(
+        let file_contents = fs::read_to_string(&location).context(
             "Could not find config file in specified location. \
 If running for the first time, run `solana-exporter generate` to initialise the config file \
 and then put real values there.",
         )?;
 
-        toml::from_str::<ExporterConfig>(&file_contents)
-    }?;
+        let mtime = fs::metadata(&location)?
+            .modified()?
+            .duration_since(UNIX_EPOCH)?
+            .as_secs();
 
-    let exporter = prometheus_exporter::start(config.target)?;
-    let duration = Duration::from_secs(1);
-    let client = RpcClient::new(config.rpc.clone());
+        let mut config = toml::from_str::<ExporterConfig>(&file_contents)
+            .context("Could not parse config file")?;
 
-    let geolocation_cache =
-        GeolocationCache::new(persistent_database.tree(GEO_DB_CACHE_TREE_NAME)?);
-    let rewards_cache = RewardsCache::new(
-        persistent_database.tree(EPOCH_REWARDS_TREE_NAME)?,
-        persistent_database.tree(APY_TREE_NAME)?,
-        persistent_database.tree(EPOCH_LENGTH_TREE_NAME)?,
-        persistent_database.tree(EPOCH_VOTER_APY_TREE_NAME)?,
+        // CLI flags take precedence over environment variables, which take precedence over the
+        // config file.
+        if let Some(rpc) = cli_configs
+            .value_of("rpc")
+            .map(str::to_string)
+            .or_else(|| env::var(RPC_ENV_VAR).ok())
+        {
+            config.rpc = rpc;
+        }
+        if let Some(target) = cli_configs
+            .value_of("target")
+            .map(str::to_string)
+            .or_else(|| env::var(TARGET_ENV_VAR).ok())
+        {
+            config.target = target
+                .parse()
+                .with_context(|| format!("Invalid target address {}", target))?;
+        }
+
+        // MaxMind credentials from the environment or the password file take precedence over the
+        // config file, so that they need not be stored in it.
+        let maxmind_username = env::var(MAXMIND_USERNAME_ENV_VAR)
+            .ok()
+            .or_else(|| config.maxmind.as_ref().map(|m| m.username().to_string()));
+        let maxmind_password = match env::var(MAXMIND_PASSWORD_ENV_VAR) {
+            Ok(password) => Some(password),
+            Err(_) => match &config.maxmind_password_file {
+                Some(path) => Some(
+                    fs::read_to_string(path)
+                        .with_context(|| {
+                            format!("Could not read MaxMind password file {:?}", path)
+                        })?
+                        .trim_end()
+                        .to_string(),
+                ),
+                None => config.maxmind.as_ref().map(|m| m.password().to_string()),
+            },
+        };
+        if maxmind_username.is_some() || maxmind_password.is_some() {
+            // A missing username or password is reported by the validation below.
+            config.maxmind = Some(MaxMindAPIKey::new(
+                &maxmind_username.unwrap_or_default(),
+                &maxmind_password.unwrap_or_default(),
+            ));
+        }
+
+        if let Err(errors) = config.validate() {
+            for e in &errors {
+                error!("Invalid config: {}", e);
+            }
+            return Err(anyhow!("Config file has {} problem(s)", errors.len()));
+        }
+
+        (config, mtime)
+    };
+
+    if cli_configs.subcommand_matches("check").is_some() {
+        return check_rpc(&config);
+    }
+
+    // Show warning if database not found, since sled will make a new file?
+    if !database_location.exists() {
+        warn!("Database could not found at specified location. A new one will be generated!")
+    }
+    let persistent_database = PersistentDatabase::new(&database_location)?;
+    // Caches with a location of their own, e.g., on a tmpfs, are kept in separate databases.
+    let geolocation_database = config
+        .geolocation_db_path
+        .as_deref()
+        .map(PersistentDatabase::new)
+        .transpose()
+        .context("Could not open the geolocation database")?;
+    let rewards_database = config
+        .rewards_db_path
+        .as_deref()
+        .map(PersistentDatabase::new)
+        .transpose()
+        .context("Could not open the rewards database")?;
+    let databases: Vec<&PersistentDatabase> = std::iter::once(&persistent_database)
+        .chain(&geolocation_database)
+        .chain(&rewards_database)
+        .collect();
+
+    let client = RpcClient::new_with_timeout_and_commitment(
+        config.rpc.clone(),
+        Duration::from_secs(config.rpc_timeout_seconds),
+        config.commitment.into(),
     );
 
-    let vote_accounts_whitelist = config.vote_account_whitelist.unwrap_or_default();
-    let staking_account_whitelist = config.staking_account_whitelist.unwrap_or_default();
+    let local_geolocation_database = config
+        .maxmind_db_path
+        .as_ref()
+        .map(|path| LocalGeolocationDatabase::open(path, config.maxmind_asn_db_path.as_deref()))
+        .transpose()?;
+    let fallback_geolocation_database = config
+        .geolocation_fallback_db_path
+        .as_ref()
+        .map(|path| LocalGeolocationDatabase::open(path, None))
+        .transpose()?;
+    let geolocation_cache = GeolocationCache::new(
+        geolocation_database
+            .as_ref()
+            .unwrap_or(&persistent_database)
+            .tree(GEO_DB_CACHE_TREE_NAME)?,
+    );
+    let rewards_cache =
+        open_rewards_cache(rewards_database.as_ref().unwrap_or(&persistent_database))?;
+    let skipped_slots_cache = SkippedSlotsCache::new(
+        persistent_database.tree(SKIPPED_SLOTS_TREE_NAME)?,
+        persistent_database.tree(RECENT_SLOTS_TREE_NAME)?,
+        persistent_database.tree(LEADER_SCHEDULE_TREE_NAME)?,
+        persistent_database.tree(LEADER_SLOT_COUNTS_TREE_NAME)?,
+        persistent_database.tree(EPOCH_SLOT_COUNTS_TREE_NAME)?,
+    );
+    let vote_accounts_cache = VoteAccountsCache::new(
+        persistent_database.tree(VOTE_ACCOUNT_IDENTITIES_TREE_NAME)?,
+        persistent_database.tree(VOTE_ACCOUNT_COMMISSIONS_TREE_NAME)?,
+        persistent_database.tree(VOTE_ACCOUNT_AUTHORITIES_TREE_NAME)?,
+    );
+    let webhook_cache = WebhookCache::new(persistent_database.tree(WEBHOOK_TREE_NAME)?);
 
-    let gauges = PrometheusGauges::new(vote_accounts_whitelist.clone());
-    let mut skipped_slots_monitor =
-        SkippedSlotsMonitor::new(&client, &gauges.leader_slots, &gauges.skipped_slot_percent);
-    let mut rewards_monitor = RewardsMonitor::new(
+    let inline_vote_accounts_whitelist = config.vote_account_whitelist.clone().unwrap_or_default();
+    let mut whitelist_file = config.pubkey_whitelist_file.clone().map(WhitelistFile::new);
+    let vote_accounts_whitelist = match &mut whitelist_file {
+        Some(file) => {
+            file.reload()
+                .context("Could not read the pubkey whitelist file")?;
+            file.union(&inline_vote_accounts_whitelist)
+        }
+        None => inline_vote_accounts_whitelist.clone(),
+    };
+    let staking_account_whitelist = config.staking_account_whitelist.clone().unwrap_or_default();
+    let identity_pubkeys = config.identity_pubkeys.clone().unwrap_or_default();
+
+    let gauges = PrometheusGauges::new(
+        vote_accounts_whitelist.clone(),
+        PubkeyLabels::new(config.pubkey_label_salt.clone()),
+        config.rank_filter,
+        maxmind_client(
+            config.http_proxy.as_deref(),
+            Duration::from_secs(config.maxmind_timeout_seconds),
+        )?,
+        config.metric_prefix.clone(),
+        config.track_process_metrics,
+    );
+    gauges.config_mtime.set(config_mtime as i64);
+    gauges
+        .register_shadow_metrics(&config.shadow_metrics)
+        .context("Failed to register shadow metrics")?;
+    let skipped_slots_monitor = SkippedSlotsMonitor::new(
         &client,
-        &gauges.current_staking_apy,
-        &gauges.average_staking_apy,
-        &gauges.validator_rewards,
+        &gauges,
+        &skipped_slots_cache,
+        config.skip_rate_window_slots,
+        config.rpc_max_retries,
+    )?;
+    let rewards_monitor = RewardsMonitor::new(
+        &client,
+        &gauges,
         &rewards_cache,
         &staking_account_whitelist,
-        &vote_accounts_whitelist,
+        vote_accounts_whitelist.clone(),
+        &config,
     );
+    let vote_accounts_monitor = VoteAccountsMonitor::new(&gauges, &vote_accounts_cache);
+
+    if let Err(e) = gauges.export_cluster_info(&client) {
+        warn!("Could not export the cluster info: {:?}", e);
+    }
+
+    // The local database is preferred over the MaxMind API if both are configured.
+    let geolocation_backend = match (&local_geolocation_database, &config.maxmind) {
+        _ if !config.enable_geolocation => None,
+        (Some(database), _) => Some(GeolocationBackend::Local(database)),
+        (None, Some(maxmind)) => Some(GeolocationBackend::Api(maxmind)),
+        (None, None) => None,
+    };
+    let geolocation_sources = geolocation_backend.map(|primary| GeolocationSources {
+        primary,
+        fallback: fallback_geolocation_database.as_ref(),
+    });
+    if let Some(sources) = geolocation_sources.filter(|_| config.export_rpc_location) {
+        if let Err(e) = gauges
+            .export_rpc_location(&config.rpc, &geolocation_cache, sources, &config)
+            .await
+        {
+            warn!("Could not export the location of the RPC node: {:?}", e);
+        }
+    }
+
+    // When asked to terminate, the exporter stops waiting right away, and an update stops at its
+    // next check between RPC calls since the calls themselves block. The database is flushed
+    // before exiting.
+    let (shutdown_sender, mut shutdown) = watch::channel(false);
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::spawn(async move {
+        sigterm.recv().await;
+        let _ = shutdown_sender.send(true);
+    });
+
+    let mut exporter_loop = ExporterLoop {
+        config: &config,
+        client: &client,
+        gauges: &gauges,
+        skipped_slots_monitor,
+        rewards_monitor,
+        vote_accounts_monitor,
+        geolocation_cache: &geolocation_cache,
+        geolocation_sources,
+        geolocation_interval: config.geolocation_interval_seconds.map(Duration::from_secs),
+        webhook: config.webhook.clone().map(Webhook::new).transpose()?,
+        webhook_cache: &webhook_cache,
+        shutdown: shutdown.clone(),
+        whitelist_file,
+        inline_vote_accounts_whitelist,
+        vote_accounts_whitelist,
+        identity_pubkeys,
+        last_geolocation: None,
+        last_stake_states: None,
+    };
+
+    // Update the metrics once, print them and exit, for pushing or batch scraping.
+    if cli_configs.is_present("once") {
+        if !exporter_loop.run_once().await? {
+            return Err(anyhow!("RPC node returned an empty response"));
+        }
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+        io::stdout().write_all(&buffer)?;
+        exporter_loop.finish().await;
+        for database in &databases {
+            database.flush()?;
+        }
+        return Ok(());
+    }
+
+    let tls = match (&config.tls_cert_path, &config.tls_key_path) {
+        (Some(cert_path), Some(key_path)) => Some((cert_path.as_path(), key_path.as_path())),
+        _ => None,
+    };
+    let credentials = match (&config.metrics_username, &config.metrics_password) {
+        (Some(username), Some(password)) => Some((username.as_str(), password.as_str())),
+        _ => None,
+    };
+    let exporter = MetricsServer::start(config.target, tls, credentials)?;
+    let last_success = LastSuccess::default();
+    if let Some(health_target) = config.health_target {
+        health::start(
+            health_target,
+            last_success.clone(),
+            config.health_max_age_seconds,
+        )?;
+    }
+    let duration = Duration::from_secs(config.scrape_interval_seconds);
 
     loop {
-        let _guard = exporter.wait_duration(duration);
+        tokio::select! {
+            _ = shutdown.changed() => break,
+            _ = tokio::time::sleep(duration) => {}
+        }
+        let _guard = exporter.lock_updates();
         debug!("Updating metrics");
+        gauges.uptime.set(start_time.elapsed().as_secs_f64());
+
+        // The update mostly consists of blocking RPC calls and retry delays, so the runtime is told
+        // to move its other tasks off this thread in the meantime.
+        let result = task::block_in_place(|| Handle::current().block_on(exporter_loop.run_once()));
+        // A failed iteration is retried in the next one rather than stopping the exporter.
+        match result {
+            Ok(true) => last_success.update(),
+            Ok(false) => {}
+            Err(e) => error!("Failed to update metrics: {:?}", e),
+        }
+        if *shutdown.borrow() {
+            break;
+        }
+    }
+
+    info!("Received SIGTERM, flushing the database");
+    exporter_loop.finish().await;
+    for database in &databases {
+        database.flush()?;
+    }
+    Ok(())
+}
+
+/// State of the main loop that is carried over from one iteration to the next.
+struct ExporterLoop<'a> {
+    config: &'a ExporterConfig,
+    client: &'a RpcClient,
+    gauges: &'a PrometheusGauges,
+    skipped_slots_monitor: SkippedSlotsMonitor<'a>,
+    rewards_monitor: RewardsMonitor<'a>,
+    vote_accounts_monitor: VoteAccountsMonitor<'a>,
+    geolocation_cache: &'a GeolocationCache,
+    geolocation_sources: Option<GeolocationSources<'a>>,
+    geolocation_interval: Option<Duration>,
+    webhook: Option<Webhook>,
+    webhook_cache: &'a WebhookCache,
+    /// Set to `true` when the exporter is asked to terminate.
+    shutdown: watch::Receiver<bool>,
+    whitelist_file: Option<WhitelistFile>,
+    inline_vote_accounts_whitelist: Whitelist,
+    vote_accounts_whitelist: Whitelist,
+    identity_pubkeys: Whitelist,
+    last_geolocation: Option<Instant>,
+    last_stake_states: Option<Instant>,
+}
+
+impl ExporterLoop<'_> {
+    /// Waits for the background work of the loop to finish before exiting.
+    async fn finish(&mut self) {
+        if let Some(webhook) = &mut self.webhook {
+            webhook.finish().await;
+        }
+    }
+
+    /// Returns `true` iff the exporter was asked to terminate.
+    fn shutting_down(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    /// Runs a single iteration of the main loop, updating all metrics. Returns `false` if the update
+    /// was skipped because the RPC node returned an empty response, or was cut short between RPC
+    /// calls because the exporter was asked to terminate.
+    async fn run_once(&mut self) -> anyhow::Result<bool> {
+        let loop_start = Instant::now();
+
+        self.gauges.export_rpc_status(self.client);
 
         // Get metrics we need
-        let epoch_info = client.get_epoch_info()?;
-        let nodes = client.get_cluster_nodes()?;
-        let vote_accounts = client.get_vote_accounts()?;
-        let node_whitelist = rpc_extra::node_pubkeys(&vote_accounts_whitelist, &vote_accounts);
+        let call_start = Instant::now();
+        let epoch_info = retry_with_backoff(
+            || {
+                self.gauges
+                    .count_rpc_error("get_epoch_info", self.client.get_epoch_info())
+            },
+            self.config.rpc_max_retries,
+        )?;
+        self.gauges
+            .rpc_call_duration
+            .with_label_values(&["get_epoch_info"])
+            .set(call_start.elapsed().as_secs_f64());
+        let call_start = Instant::now();
+        let nodes = retry_with_backoff(
+            || {
+                self.gauges
+                    .count_rpc_error("get_cluster_nodes", self.client.get_cluster_nodes())
+            },
+            self.config.rpc_max_retries,
+        )?;
+        self.gauges
+            .rpc_call_duration
+            .with_label_values(&["get_cluster_nodes"])
+            .set(call_start.elapsed().as_secs_f64());
+        let call_start = Instant::now();
+        let vote_accounts = retry_with_backoff(
+            || {
+                self.gauges
+                    .count_rpc_error("get_vote_accounts", self.client.get_vote_accounts())
+            },
+            self.config.rpc_max_retries,
+        )?;
+        self.gauges
+            .rpc_call_duration
+            .with_label_values(&["get_vote_accounts"])
+            .set(call_start.elapsed().as_secs_f64());
+
+        // Skip this round rather than exporting zeroes while the RPC node is starting up.
+        if rpc_extra::is_empty_epoch_info(&epoch_info) {
+            warn!("Skipping update: RPC node returned empty epoch info");
+            self.gauges
+                .empty_response
+                .with_label_values(&["getEpochInfo"])
+                .inc();
+            return Ok(false);
+        }
+        if rpc_extra::is_empty_vote_accounts(&vote_accounts) {
+            warn!("Skipping update: RPC node returned no vote accounts");
+            self.gauges
+                .empty_response
+                .with_label_values(&["getVoteAccounts"])
+                .inc();
+            return Ok(false);
+        }
+
+        if self.shutting_down() {
+            return Ok(false);
+        }
+
+        // Changes to the whitelist file take effect without a restart.
+        if let Some(file) = &mut self.whitelist_file {
+            match file.reload() {
+                Ok(true) => {
+                    self.vote_accounts_whitelist = file.union(&self.inline_vote_accounts_whitelist);
+                    info!(
+                        "Reloaded the pubkey whitelist file, {} pubkeys whitelisted",
+                        self.vote_accounts_whitelist.0.len()
+                    );
+                    self.gauges
+                        .set_vote_accounts_whitelist(self.vote_accounts_whitelist.clone());
+                    self.rewards_monitor
+                        .set_vote_accounts_whitelist(self.vote_accounts_whitelist.clone());
+                }
+                Ok(false) => {}
+                Err(e) => warn!("Could not reload the pubkey whitelist file: {:?}", e),
+            }
+        }
 
-        gauges
-            .export_vote_accounts(&vote_accounts)
+        let node_whitelist = rpc_extra::node_pubkeys(&self.vote_accounts_whitelist, &vote_accounts);
+
+        // Summarise the finished epoch as of its last update, which is stored so that a restart
+        // across the epoch boundary does not drop the summary.
+        if let Some(webhook) = &mut self.webhook {
+            if let Err(e) = webhook.send_finished_epoch(self.webhook_cache, epoch_info.epoch) {
+                warn!("Could not summarise the finished epoch: {:?}", e);
+            }
+        }
+
+        self.gauges
+            .export_vote_accounts(&vote_accounts, epoch_info.absolute_slot)
             .context("Failed to export vote account metrics")?;
-        gauges
-            .export_epoch_info(&epoch_info, &client)
+        if self.config.export_vote_account_balances {
+            self.gauges
+                .export_vote_account_balances(
+                    &vote_accounts,
+                    self.client,
+                    self.config.account_fetch_chunk_size,
+                )
+                .context("Failed to export vote account balances")?;
+        }
+        self.vote_accounts_monitor
+            .export_identity_changes(&vote_accounts, &self.vote_accounts_whitelist)
+            .context("Failed to export vote account identity changes")?;
+        self.vote_accounts_monitor
+            .export_commission_changes(
+                &vote_accounts,
+                &self.vote_accounts_whitelist,
+                epoch_info.epoch,
+            )
+            .context("Failed to export commission changes")?;
+        if self.config.track_vote_authorities {
+            self.vote_accounts_monitor
+                .export_authority_changes(
+                    &vote_accounts,
+                    &self.vote_accounts_whitelist,
+                    epoch_info.epoch,
+                    self.client,
+                    self.config.account_fetch_chunk_size,
+                )
+                .context("Failed to export vote authority changes")?;
+        }
+        self.gauges
+            .export_epoch_credits(&vote_accounts, &epoch_info)
+            .context("Failed to export epoch credits")?;
+        self.gauges
+            .export_epoch_info(&epoch_info, self.client)
             .context("Failed to export epoch info metrics")?;
-        gauges.export_nodes_info(&nodes, &client, &node_whitelist)?;
-        if let Some(maxmind) = config.maxmind.clone() {
-            // If the MaxMind API is configured, submit queries for any uncached IPs.
-            gauges
+        if let Some(sample_blocks) = self.config.slot_time_sample_blocks {
+            self.gauges
+                .export_slot_times(self.client, &epoch_info, sample_blocks)
+                .context("Failed to export slot times")?;
+        }
+        if self.config.track_supply {
+            self.gauges
+                .export_supply_info(self.client)
+                .context("Failed to export supply info")?;
+        }
+        if self.shutting_down() {
+            return Ok(false);
+        }
+        self.gauges.export_nodes_info(
+            &nodes,
+            &vote_accounts,
+            self.client,
+            &node_whitelist,
+            &self.identity_pubkeys,
+            self.config.account_fetch_chunk_size,
+        )?;
+        let geolocation_due = match (self.last_geolocation, self.geolocation_interval) {
+            (Some(last), Some(interval)) => last.elapsed() >= interval,
+            _ => true,
+        };
+        if let Some(sources) = self.geolocation_sources.filter(|_| geolocation_due) {
+            self.gauges
                 .export_ip_addresses(
                     &nodes,
                     &vote_accounts,
-                    &geolocation_cache,
-                    &maxmind,
+                    self.geolocation_cache,
+                    sources,
                     &node_whitelist,
+                    self.config,
                 )
                 .await
                 .context("Failed to export IP address info metrics")?;
+            self.last_geolocation = Some(Instant::now());
         }
-        skipped_slots_monitor
+        if self.shutting_down() {
+            return Ok(false);
+        }
+        self.skipped_slots_monitor
             .export_skipped_slots(&epoch_info, &node_whitelist)
             .context("Failed to export skipped slots")?;
-        rewards_monitor
+        self.gauges
+            .export_current_leader(
+                &nodes,
+                &node_whitelist,
+                self.skipped_slots_monitor
+                    .current_leader(epoch_info.slot_index),
+            )
+            .context("Failed to export current leader")?;
+        self.gauges
+            .export_performance_discrepancy(&vote_accounts, &epoch_info)
+            .context("Failed to export performance discrepancy")?;
+        if self.shutting_down() {
+            return Ok(false);
+        }
+        self.rewards_monitor
             .export_rewards(&epoch_info)
             .context("Failed to export rewards")?;
+        if self.config.track_stake_states
+            && self
+                .last_stake_states
+                .map_or(true, |last| last.elapsed() >= STAKE_STATES_INTERVAL)
+        {
+            self.gauges
+                .export_stake_states(self.client, &epoch_info)
+                .context("Failed to export stake states")?;
+            self.last_stake_states = Some(Instant::now());
+        }
+
+        if self.webhook.is_some() {
+            self.webhook_cache
+                .set_pending_summary(&EpochSummary::from_gauges(epoch_info.epoch, self.gauges))
+                .context("Failed to store the epoch summary")?;
+        }
+
+        self.gauges
+            .scrape_duration
+            .set(loop_start.elapsed().as_secs_f64());
+
+        Ok(true)
+    }
+}
+
+/// Returns the default directory for files of the kind given by the XDG base directory variable
+/// `xdg_var`. An existing `~/.solana-exporter` is always used so that existing setups keep working.
+/// Otherwise, `$<xdg_var>/solana-exporter` is used if the variable is set, then `~/.solana-exporter`,
+/// and `system_dir` if there is no home directory, such as when running as root without `$HOME`.
+fn default_dir(xdg_var: &str, system_dir: &str) -> PathBuf {
+    let home_dir = dirs::home_dir().map(|home| home.join(EXPORTER_DATA_DIR));
+    if let Some(dir) = home_dir.as_ref().filter(|dir| dir.is_dir()) {
+        return dir.clone();
+    }
+    if let Some(xdg_dir) = env::var_os(xdg_var).filter(|dir| !dir.is_empty()) {
+        return PathBuf::from(xdg_dir).join(XDG_DIR_NAME);
+    }
+    home_dir.unwrap_or_else(|| PathBuf::from(system_dir))
+}
+
+/// Checks that the RPC node in `config` is reachable and healthy, and that the whitelisted vote
+/// accounts exist. Prints the results and fails if any check fails.
+fn check_rpc(config: &ExporterConfig) -> anyhow::Result<()> {
+    let client = RpcClient::new_with_timeout_and_commitment(
+        config.rpc.clone(),
+        Duration::from_secs(config.rpc_timeout_seconds),
+        config.commitment.into(),
+    );
+
+    client
+        .get_health()
+        .with_context(|| format!("RPC node {} is not healthy", config.rpc))?;
+    println!("RPC node {} is healthy", config.rpc);
+
+    let version = client
+        .get_version()
+        .context("Could not get RPC node version")?;
+    println!("RPC node version: {}", version.solana_core);
+
+    let epoch_info = client
+        .get_epoch_info()
+        .context("Could not get epoch info")?;
+    println!(
+        "Current epoch: {}, slot {} of {}",
+        epoch_info.epoch, epoch_info.slot_index, epoch_info.slots_in_epoch
+    );
+
+    if let Some(whitelist) = &config.vote_account_whitelist {
+        let vote_accounts = client
+            .get_vote_accounts()
+            .context("Could not get vote accounts")?;
+        let missing: Vec<_> = whitelist
+            .0
+            .iter()
+            .filter(|pubkey| {
+                !vote_accounts
+                    .current
+                    .iter()
+                    .chain(vote_accounts.delinquent.iter())
+                    .any(|v| v.vote_pubkey == **pubkey)
+            })
+            .collect();
+        if !missing.is_empty() {
+            return Err(anyhow!(
+                "Whitelisted vote accounts not found in the cluster: {:?}",
+                missing
+            ));
+        }
+        println!("All {} whitelisted vote accounts found", whitelist.0.len());
+    }
+
+    Ok(())
+}
+
+/// Opens the rewards cache in the trees of `persistent_database`.
+fn open_rewards_cache(persistent_database: &PersistentDatabase) -> sled::Result<RewardsCache> {
+    Ok(RewardsCache::new(
+        persistent_database.tree(EPOCH_REWARDS_TREE_NAME)?,
+        persistent_database.tree(APY_TREE_NAME)?,
+        persistent_database.tree(EPOCH_LENGTH_TREE_NAME)?,
+        persistent_database.tree(EPOCH_VOTER_APY_TREE_NAME)?,
+        persistent_database.tree(EPOCH_DELEGATOR_COUNT_TREE_NAME)?,
+    ))
+}
+
+/// Removes the cached rewards data of epochs before `before_epoch` from the database at `location`
+/// and prints how many keys were removed from each tree.
+fn prune_database(location: &Path, before_epoch: Epoch) -> anyhow::Result<()> {
+    if !location.exists() {
+        return Err(anyhow!("Database not found at {:?}", location));
+    }
+    let persistent_database = PersistentDatabase::new(location)?;
+    let rewards_cache = open_rewards_cache(&persistent_database)?;
+
+    for (tree_name, removed) in rewards_cache.prune_epochs_before(before_epoch)? {
+        println!("Removed {} keys from {}", removed, tree_name);
     }
+
+    // sled reclaims the space of removed keys by itself once they are flushed.
+    let flushed = persistent_database.flush()?;
+    println!("Flushed {} bytes to disk", flushed);
+    Ok(())
 }
@@ -1,7 +1,7 @@
 use crate::persistent_database::metadata::Metadata;
 use crate::SOLANA_EXPORTER_VERSION;
 use anyhow::Context;
-use log::warn;
+use log::{error, warn};
 use std::path::Path;
 use std::str::FromStr;
 
@@ -47,8 +47,22 @@ impl PersistentDatabase {
         self.database.open_tree(name)
     }
 
+    /// Flushes all dirty data to disk. Returns the number of bytes flushed.
+    pub fn flush(&self) -> sled::Result<usize> {
+        self.database.flush()
+    }
+
     /// Returns metadata for the database.
     pub fn metadata(&self) -> &Metadata {
         &self.metadata
     }
 }
+
+impl Drop for PersistentDatabase {
+    /// Flushes the database on every exit path, including errors.
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            error!("Could not flush the database: {:?}", e);
+        }
+    }
+}
@@ -12,6 +12,7 @@ pub const EPOCH_REWARDS_TREE_NAME: &str = "epoch_rewards";
 pub const APY_TREE_NAME: &str = "apy";
 pub const EPOCH_LENGTH_TREE_NAME: &str = "epoch_length";
 pub const EPOCH_VOTER_APY_TREE_NAME: &str = "epoch_voter_apy";
+pub const EPOCH_DELEGATOR_COUNT_TREE_NAME: &str = "epoch_delegator_count";
 
 #[derive(Copy, Clone, Serialize, Deserialize)]
 struct ApyTreeKey(Epoch, Pubkey);
@@ -25,6 +26,7 @@ pub struct RewardsCache {
     apy_tree: sled::Tree,
     epoch_length_tree: sled::Tree,
     epoch_voter_apy_tree: sled::Tree,
+    epoch_delegator_count_tree: sled::Tree,
 }
 
 impl RewardsCache {
@@ -34,12 +36,14 @@ impl RewardsCache {
         apy_tree: sled::Tree,
         epoch_length_tree: sled::Tree,
         epoch_voter_apy_tree: sled::Tree,
+        epoch_delegator_count_tree: sled::Tree,
     ) -> Self {
         Self {
             epoch_rewards_tree,
             apy_tree,
             epoch_length_tree,
             epoch_voter_apy_tree,
+            epoch_delegator_count_tree,
         }
     }
 
@@ -82,6 +86,11 @@ impl RewardsCache {
             .context("could not deserialize fetched epoch rewards")
     }
 
+    /// Returns the number of epochs whose rewards are cached.
+    pub fn epoch_rewards_count(&self) -> usize {
+        self.epoch_rewards_tree.len()
+    }
+
     /// Adds a set of staking APY data of an epoch.
     pub fn add_epoch_data(&self, epoch: Epoch, apys: PubkeyVoterApyMapping) -> anyhow::Result<()> {
         for (pubkey, (voter, apy)) in apys {
@@ -109,6 +118,46 @@ impl RewardsCache {
         }
     }
 
+    /// Removes all cached data of epochs before `epoch`. Returns the number of removed keys per tree.
+    pub fn prune_epochs_before(&self, epoch: Epoch) -> anyhow::Result<Vec<(&'static str, usize)>> {
+        let mut removed = Vec::new();
+        for (name, tree) in &[
+            (EPOCH_REWARDS_TREE_NAME, &self.epoch_rewards_tree),
+            (EPOCH_LENGTH_TREE_NAME, &self.epoch_length_tree),
+            (EPOCH_VOTER_APY_TREE_NAME, &self.epoch_voter_apy_tree),
+            (
+                EPOCH_DELEGATOR_COUNT_TREE_NAME,
+                &self.epoch_delegator_count_tree,
+            ),
+        ] {
+            // Keys of these trees are big-endian epochs, hence ordered by epoch.
+            let mut count = 0;
+            for kv in tree.range(..epoch.to_be_bytes()) {
+                let (k, _) = kv?;
+                tree.remove(k)
+                    .context("could not remove epoch data from database")?;
+                count += 1;
+            }
+            removed.push((*name, count));
+        }
+
+        // Keys of the APY tree are not ordered by epoch, so they all have to be checked.
+        let mut count = 0;
+        for kv in self.apy_tree.iter() {
+            let (k, _) = kv?;
+            let key: ApyTreeKey = bincode::deserialize(&k)?;
+            if key.0 < epoch {
+                self.apy_tree
+                    .remove(k)
+                    .context("could not remove APY data from database")?;
+                count += 1;
+            }
+        }
+        removed.push((APY_TREE_NAME, count));
+
+        Ok(removed)
+    }
+
     /// Adds an epoch's hashmap of voter APY mapping.
     pub fn add_epoch_voter_apy(
         &self,
@@ -133,4 +182,29 @@ impl RewardsCache {
             .transpose()
             .context("could not deserialize fetched epoch voter apy")
     }
+
+    /// Adds an epoch's hashmap of the number of stake accounts delegated to each voter.
+    pub fn add_epoch_delegator_counts(
+        &self,
+        epoch: Epoch,
+        delegator_counts: &HashMap<Pubkey, u64>,
+    ) -> anyhow::Result<()> {
+        self.epoch_delegator_count_tree
+            .insert(epoch.to_be_bytes(), bincode::serialize(delegator_counts)?)
+            .context("could not insert delegator counts into database")?;
+        Ok(())
+    }
+
+    /// Gets an epoch's hashmap of the number of stake accounts delegated to each voter.
+    pub fn get_epoch_delegator_counts(
+        &self,
+        epoch: Epoch,
+    ) -> anyhow::Result<Option<HashMap<Pubkey, u64>>> {
+        self.epoch_delegator_count_tree
+            .get(epoch.to_be_bytes())
+            .context("could not fetch delegator counts from database")?
+            .map(|x| bincode::deserialize(&x))
+            .transpose()
+            .context("could not deserialize fetched delegator counts")
+    }
 }
@@ -1,17 +1,17 @@
-use crate::config::Whitelist;
+use crate::config::{ExporterConfig, Whitelist};
+use crate::gauges::PrometheusGauges;
 use crate::rewards::caching::{PubkeyVoterApyMapping, RewardsCache};
-use crate::rpc_extra::with_first_block;
+use crate::rpc_extra::{
+    block_config, retry_with_backoff, with_first_block, MIN_EXTRAPOLATION_SLOTS,
+};
 use anyhow::anyhow;
-use log::debug;
-use prometheus_exporter::prometheus::{GaugeVec, IntGaugeVec};
+use log::{debug, warn};
 use serde::{Deserialize, Serialize};
-use solana_client::rpc_client::RpcClient;
-use solana_client::rpc_config::RpcBlockConfig;
+use solana_client::{client_error::ClientError, rpc_client::RpcClient};
 use solana_runtime::bank::RewardType;
-use solana_sdk::account::Account;
 use solana_sdk::{clock::Epoch, epoch_info::EpochInfo, pubkey::Pubkey};
 use solana_stake_program::stake_state::StakeState;
-use solana_transaction_status::{Reward, Rewards, TransactionDetails, UiTransactionEncoding};
+use solana_transaction_status::{Reward, Rewards, UiConfirmedBlock};
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::u64;
 use time::OffsetDateTime;
@@ -27,9 +27,6 @@ const DAYS_IN_YEAR: u64 = 365;
 /// A default epoch length to use in case it cannot be found.
 const DEFAULT_EPOCH_LENGTH: f64 = 3.0;
 
-/// Maximum number of epochs to look back, INCLUSIVE of the current epoch.
-const MAX_EPOCH_LOOKBACK: u64 = 5;
-
 pub(crate) type VoterEpoch = (Pubkey, Epoch);
 type VoterEpochRewardMap = HashMap<VoterEpoch, Reward>;
 type VoterEpochApyMap = HashMap<VoterEpoch, f64>;
@@ -61,7 +58,7 @@ struct ValidatorReward {
 pub struct VoterApy {
     /// APY for the current epoch
     current_apy: f64,
-    /// APY over the last `MAX_EPOCH_LOOKBACK` epochs.
+    /// APY over the last `apy_lookback_epochs` epochs.
     average_apy: f64,
 }
 
@@ -69,49 +66,79 @@ pub struct VoterApy {
 pub struct RewardsMonitor<'a> {
     /// Shared Solana RPC client.
     client: &'a RpcClient,
-    /// Prometheus current staking APY gauge.
-    current_staking_apy: &'a GaugeVec,
-    /// Prometheus average staking APY gauge.
-    average_staking_apy: &'a GaugeVec,
-    /// Prometheus cumulative validator rewards gauge.
-    validator_rewards: &'a IntGaugeVec,
+    /// Prometheus gauges.
+    gauges: &'a PrometheusGauges,
     /// Caching database for rewards
     cache: &'a RewardsCache,
     /// The whitelist of staking account pubkeys constraining APY calculations.
     staking_account_whitelist: &'a Whitelist,
     /// The whitelist of vote account pubkeys
-    vote_accounts_whitelist: &'a Whitelist,
+    vote_accounts_whitelist: Whitelist,
+    /// Number of past epochs to keep in the cache, or `None` to keep all of them.
+    retention_epochs: Option<u64>,
+    /// Whether to export the APY net of inflation dilution.
+    real_apy: bool,
+    /// Number of epochs over which the average APY is calculated, inclusive of the current epoch.
+    lookback_epochs: u64,
+    /// Maximum number of accounts fetched in a single RPC call.
+    account_fetch_chunk_size: usize,
+    /// Maximum number of retries of a failed RPC call.
+    max_retries: u32,
 }
 
 impl<'a> RewardsMonitor<'a> {
     /// Initialises a new rewards monitor.
     pub fn new(
         client: &'a RpcClient,
-        current_staking_apy: &'a GaugeVec,
-        average_staking_apy: &'a GaugeVec,
-        validator_rewards: &'a IntGaugeVec,
+        gauges: &'a PrometheusGauges,
         rewards_cache: &'a RewardsCache,
         staking_account_whitelist: &'a Whitelist,
-        vote_accounts_whitelist: &'a Whitelist,
+        vote_accounts_whitelist: Whitelist,
+        config: &ExporterConfig,
     ) -> Self {
+        gauges
+            .cached_reward_epochs
+            .set(rewards_cache.epoch_rewards_count() as i64);
         Self {
             client,
-            current_staking_apy,
-            average_staking_apy,
-            validator_rewards,
+            gauges,
             cache: rewards_cache,
             staking_account_whitelist,
             vote_accounts_whitelist,
+            retention_epochs: config.rewards_retention_epochs,
+            real_apy: config.real_staking_apy,
+            lookback_epochs: config.apy_lookback_epochs,
+            account_fetch_chunk_size: config.account_fetch_chunk_size,
+            max_retries: config.rpc_max_retries,
         }
     }
 
+    /// Replaces the whitelist of vote account pubkeys. APYs already calculated for the current
+    /// epoch are not recalculated.
+    pub fn set_vote_accounts_whitelist(&mut self, whitelist: Whitelist) {
+        self.vote_accounts_whitelist = whitelist;
+    }
+
     /// Exports reward metrics. APY values will not be re-calculated more than once an epoch.
     pub fn export_rewards(&mut self, epoch_info: &EpochInfo) -> anyhow::Result<()> {
         let epoch = epoch_info.epoch;
 
         // Possible that rewards haven't shown up yet for this epoch
-        if self.get_rewards_for_epoch(epoch)?.is_some() {
+        let rewards = self.get_rewards_for_epoch(epoch)?;
+        self.gauges.rewards_available.set(rewards.is_some() as i64);
+        if let Some(rewards) = rewards {
+            // An unusually low count hints at a truncated block.
+            self.gauges
+                .epoch_reward_entries
+                .get_metric_with_label_values(&[&epoch.to_string()])
+                .map(|c| c.set(rewards.len() as i64))?;
+
             let staking_apys = self.calculate_staking_rewards(epoch_info)?;
+            let dilution = if self.real_apy {
+                Some(self.inflation_dilution()?)
+            } else {
+                None
+            };
 
             for (
                 voter,
@@ -121,26 +148,66 @@ impl<'a> RewardsMonitor<'a> {
                 },
             ) in staking_apys
             {
-                self.current_staking_apy
-                    .get_metric_with_label_values(&[&format!("{}", voter)])
+                let label = self.gauges.pubkey_labels.label(&voter.to_string());
+                self.gauges
+                    .current_staking_apy
+                    .get_metric_with_label_values(&[&label])
                     .map(|c| c.set(current_apy))?;
-                self.average_staking_apy
-                    .get_metric_with_label_values(&[&format!("{}", voter)])
+                self.gauges
+                    .average_staking_apy
+                    .get_metric_with_label_values(&[&label])
                     .map(|c| c.set(average_apy))?;
+                self.gauges
+                    .apy_epoch
+                    .get_metric_with_label_values(&[&label])
+                    .map(|c| c.set(epoch as i64))?;
+                if let Some(dilution) = dilution {
+                    self.gauges
+                        .real_staking_apy
+                        .get_metric_with_label_values(&[&label])
+                        .map(|c| c.set(current_apy - dilution))?;
+                }
+            }
+
+            if let Some(delegator_counts) = self.cache.get_epoch_delegator_counts(epoch)? {
+                for (voter, count) in delegator_counts {
+                    let voter = voter.to_string();
+                    if self.vote_accounts_whitelist.contains(&voter) {
+                        let label = self.gauges.pubkey_labels.label(&voter);
+                        self.gauges
+                            .delegator_count
+                            .get_metric_with_label_values(&[&label])
+                            .map(|c| c.set(count as i64))?;
+                    }
+                }
             }
 
             let validator_rewards = self
                 .calculate_validator_rewards(epoch)?
                 .ok_or_else(|| anyhow!("current epoch has no rewards"))?;
             for v in validator_rewards {
-                self.validator_rewards
-                    .get_metric_with_label_values(&[&v.voter])
+                self.gauges
+                    .validator_rewards
+                    .get_metric_with_label_values(&[&self.gauges.pubkey_labels.label(&v.voter)])
                     .map(|c| c.set(v.lamports as i64))?;
             }
         }
         Ok(())
     }
 
+    /// Returns the yearly dilution of stake by newly issued tokens not paid to delegators, i.e., the
+    /// validator and foundation inflation, in percent.
+    fn inflation_dilution(&self) -> anyhow::Result<f64> {
+        let inflation = retry_with_backoff(
+            || {
+                self.gauges
+                    .count_rpc_error("get_inflation_rate", self.client.get_inflation_rate())
+            },
+            self.max_retries,
+        )?;
+        Ok((inflation.validator + inflation.foundation) * 100.0)
+    }
+
     /// Calculates the validator rewards for an epoch.
     fn calculate_validator_rewards(
         &self,
@@ -161,7 +228,8 @@ impl<'a> RewardsMonitor<'a> {
         }))
     }
 
-    /// Calculates the staking rewards for both the current epoch and the last `MAX_EPOCH_LOOKBACK` epochs.
+    /// Calculates the staking rewards for both the current epoch and the preceding epochs within
+    /// the lookback.
     fn calculate_staking_rewards(
         &self,
         current_epoch_info: &EpochInfo,
@@ -181,11 +249,32 @@ impl<'a> RewardsMonitor<'a> {
             self.cache
                 .add_epoch_voter_apy(current_epoch_info.epoch, &mapping)?;
 
+            self.prune_cache(current_epoch_info.epoch)?;
+
             Ok(mapping)
         }
     }
 
-    /// Fills `rewards` and `apys` with previous epochs' information, up to `MAX_EPOCH_LOOKBACK` epochs ago.
+    /// Removes cached epochs that are older than the retention period, which is never shorter than
+    /// the APY lookback.
+    fn prune_cache(&self, current_epoch: Epoch) -> anyhow::Result<()> {
+        if let Some(retention_epochs) = self.retention_epochs {
+            let before_epoch =
+                current_epoch.saturating_sub(retention_epochs.max(self.lookback_epochs));
+            for (tree_name, removed) in self.cache.prune_epochs_before(before_epoch)? {
+                debug!(
+                    "Pruned {} keys before epoch {} from {}",
+                    removed, before_epoch, tree_name
+                );
+            }
+            self.gauges
+                .cached_reward_epochs
+                .set(self.cache.epoch_rewards_count() as i64);
+        }
+        Ok(())
+    }
+
+    /// Fills `rewards` and `apys` with previous epochs' information, up to `lookback_epochs` epochs ago.
     fn fill_historical_epochs(
         &self,
         current_epoch_info: &EpochInfo,
@@ -195,13 +284,16 @@ impl<'a> RewardsMonitor<'a> {
         let mut rewards = HashMap::new();
         let mut apys = HashMap::new();
 
-        for epoch in (current_epoch - MAX_EPOCH_LOOKBACK)..current_epoch {
-            // Historical rewards
-            let historical_rewards = self
-                .get_rewards_for_epoch(epoch)?
-                .ok_or_else(|| anyhow!("historical epoch has no rewards"))?;
-            for reward in historical_rewards {
-                rewards.insert((reward.pubkey.parse()?, epoch), reward);
+        for epoch in current_epoch.saturating_sub(self.lookback_epochs)..current_epoch {
+            // Historical rewards. An epoch without blocks has no rewards, which leaves it out of
+            // the average APY rather than failing the whole calculation.
+            match self.get_rewards_for_epoch(epoch)? {
+                Some(historical_rewards) => {
+                    for reward in historical_rewards {
+                        rewards.insert((reward.pubkey.parse()?, epoch), reward);
+                    }
+                }
+                None => debug!("No blocks found in historical epoch {}", epoch),
             }
 
             let historical_apys = self.cache.get_epoch_apy(epoch)?.unwrap_or_default();
@@ -224,6 +316,11 @@ impl<'a> RewardsMonitor<'a> {
         apys: &mut VoterEpochApyMap,
     ) -> anyhow::Result<HashMap<Pubkey, VoterApy>> {
         let current_epoch = current_epoch_info.epoch;
+        // Staking rewards paid in an epoch were earned in the previous one, which epoch 0 lacks.
+        let previous_epoch = match current_epoch.checked_sub(1) {
+            Some(previous_epoch) => previous_epoch,
+            None => return Ok(HashMap::new()),
+        };
 
         let current_rewards = self
             .get_rewards_for_epoch(current_epoch)?
@@ -263,6 +360,13 @@ impl<'a> RewardsMonitor<'a> {
             .filter(|r| !cached_pubkeys.contains(&r.pubkey))
             .collect();
 
+        // Distinct stake accounts delegated to each voter. Cached pubkeys are not queried again, so
+        // they are counted here.
+        let mut delegators: HashMap<Pubkey, BTreeSet<Pubkey>> = HashMap::new();
+        for (pubkey, (voter, _)) in &cached_apys {
+            delegators.entry(*voter).or_default().insert(*pubkey);
+        }
+
         // Move cached pubkeys into APYs by voter
         apys.extend(
             cached_apys
@@ -277,11 +381,18 @@ impl<'a> RewardsMonitor<'a> {
             // for a given voter.
             let mut seen_voters = BTreeSet::new();
 
-            // Chunk into 100
-            for chunk in to_query.chunks(100) {
+            for chunk in to_query.chunks(self.account_fetch_chunk_size) {
                 let pubkeys: Vec<_> = chunk.iter().map(|r| r.pubkey).collect();
                 debug!("Getting {} accounts", chunk.len());
-                let account_infos = self.client.get_multiple_accounts(pubkeys.as_slice())?;
+                let account_infos = retry_with_backoff(
+                    || {
+                        self.gauges.count_rpc_error(
+                            "get_multiple_accounts",
+                            self.client.get_multiple_accounts(pubkeys.as_slice()),
+                        )
+                    },
+                    self.max_retries,
+                )?;
 
                 // For each response in chunk
                 for (reward, account_info) in chunk
@@ -289,11 +400,31 @@ impl<'a> RewardsMonitor<'a> {
                     .zip(account_infos)
                     .flat_map(|(r, oa)| oa.map(|a| (r, a)))
                 {
+                    // An account with an unexpected layout, e.g., one that was closed and reused,
+                    // must not fail the calculation for all others.
+                    let stake_state = match bincode::deserialize::<StakeState>(&account_info.data) {
+                        Ok(stake_state) => stake_state,
+                        Err(e) => {
+                            warn!(
+                                "Skipping stake account {} that could not be deserialized: {}",
+                                reward.pubkey, e
+                            );
+                            continue;
+                        }
+                    };
+
+                    if let Some(delegation) = stake_state.delegation() {
+                        delegators
+                            .entry(delegation.voter_pubkey)
+                            .or_default()
+                            .insert(reward.pubkey);
+                    }
+
                     // Calculate APY
                     if let Some(StakingApy { voter, percent }) = calculate_staking_apy(
-                        &account_info,
+                        &stake_state,
                         &mut seen_voters,
-                        self.epoch_duration_days(current_epoch - 1, current_epoch_info)?
+                        self.epoch_duration_days(previous_epoch, current_epoch_info)?
                             .unwrap_or(DEFAULT_EPOCH_LENGTH),
                         reward.lamports as u64,
                         reward.post_balance,
@@ -303,7 +434,7 @@ impl<'a> RewardsMonitor<'a> {
                     }
                 }
 
-                // Write to cache in chunks of 100 at a time.
+                // Write to cache one chunk at a time.
                 self.cache.add_epoch_data(current_epoch, queried.clone())?;
             }
 
@@ -315,7 +446,15 @@ impl<'a> RewardsMonitor<'a> {
             );
         }
 
-        // A mapping of pubkeys to APYs in the preceding `MAX_EPOCH_LOOKBACK` epochs.
+        self.cache.add_epoch_delegator_counts(
+            current_epoch,
+            &delegators
+                .into_iter()
+                .map(|(voter, stake_accounts)| (voter, stake_accounts.len() as u64))
+                .collect(),
+        )?;
+
+        // A mapping of pubkeys to APYs in the preceding `lookback_epochs` epochs.
         let mut voter_epoch_apys: HashMap<Pubkey, BTreeMap<Epoch, f64>> = HashMap::new();
         // Fill in the epoch APYs of voters.
         for ((voter, epoch), apy) in apys {
@@ -327,28 +466,37 @@ impl<'a> RewardsMonitor<'a> {
                 .or_insert_with(|| std::iter::once((*epoch, *apy)).collect());
         }
 
-        // Epoch durations up to lookback
-        let epoch_durations = (current_epoch - MAX_EPOCH_LOOKBACK + 1..=current_epoch)
-            .map(|epoch| {
+        // Epoch durations up to lookback. Epoch 0 has no previous epoch and no APY.
+        let epoch_durations = ((current_epoch + 1).saturating_sub(self.lookback_epochs)
+            ..=current_epoch)
+            .filter_map(|epoch| Some((epoch, epoch.checked_sub(1)?)))
+            .map(|(epoch, previous_epoch)| {
                 Ok((
                     epoch,
-                    self.epoch_duration_days(epoch - 1, current_epoch_info)?
+                    self.epoch_duration_days(previous_epoch, current_epoch_info)?
                         .unwrap_or(DEFAULT_EPOCH_LENGTH),
                 ))
             })
             .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
-        let duration_max_epoch_lookback: f64 = epoch_durations.values().sum();
 
         let mut voter_apys = HashMap::new();
 
-        // Calculate the current and average APY
+        // Calculate the current and average APY. Epochs without APY data of a voter, e.g., before it
+        // joined, are left out of its average instead of counting as zero.
         for (voter, epoch_apys) in voter_epoch_apys {
             let mut total_apy = 0.0;
+            let mut total_duration = 0.0;
             for (epoch, duration) in &epoch_durations {
-                let apy = *epoch_apys.get(epoch).unwrap_or(&0.0);
-                total_apy += apy * duration;
+                if let Some(apy) = epoch_apys.get(epoch) {
+                    total_apy += apy * duration;
+                    total_duration += duration;
+                }
             }
-            let average_apy = total_apy / duration_max_epoch_lookback;
+            let average_apy = if total_duration > 0.0 {
+                total_apy / total_duration
+            } else {
+                0.0
+            };
             let current_apy = *epoch_apys.get(&current_epoch).unwrap_or(&0.0);
             voter_apys.insert(
                 voter,
@@ -366,16 +514,20 @@ impl<'a> RewardsMonitor<'a> {
     /// will be extrapolated from the current average slot time.
     /// Note that this function returns the epoch number exactly as requested. For calculating
     /// rewards, remember that the rewards for epoch `N-1` are in epoch `N`.
-    /// Returns `None` if no block time is available for measurement.
+    /// Returns `None` if no block time is available for measurement, or if fewer than
+    /// `MIN_EXTRAPOLATION_SLOTS` slots of the current epoch have elapsed.
     fn epoch_duration_days(
         &self,
         epoch: Epoch,
         epoch_info: &EpochInfo,
     ) -> anyhow::Result<Option<f64>> {
-        // If it's the current epoch then we must extrapolate
+        // If it's the current epoch then we must extrapolate, which needs a few elapsed slots
         if epoch == epoch_info.epoch {
+            if epoch_info.slot_index < MIN_EXTRAPOLATION_SLOTS {
+                return Ok(None);
+            }
             let first_slot = epoch_info.absolute_slot - epoch_info.slot_index;
-            return if let Some(first_slot_time) = self.client.get_block(first_slot)?.block_time {
+            return if let Some(first_slot_time) = self.get_block(first_slot, false)?.block_time {
                 let average_slot_time = (OffsetDateTime::now_utc().unix_timestamp()
                     - first_slot_time) as f64
                     / (epoch_info.slot_index) as f64;
@@ -394,15 +546,7 @@ impl<'a> RewardsMonitor<'a> {
             let days_in_epoch = {
                 let first_block_timestamp = |ep| {
                     with_first_block(self.client, ep, |block| {
-                        let ui_confirmed_block = self.client.get_block_with_config(
-                            block,
-                            RpcBlockConfig {
-                                encoding: Some(UiTransactionEncoding::Base64),
-                                transaction_details: Some(TransactionDetails::None),
-                                rewards: Some(false),
-                                commitment: None,
-                            },
-                        )?;
+                        let ui_confirmed_block = self.get_block(block, false)?;
                         Ok(ui_confirmed_block.block_time)
                     })
                 };
@@ -434,25 +578,41 @@ impl<'a> RewardsMonitor<'a> {
             Ok(Some(rewards))
         } else {
             with_first_block(self.client, epoch, |block| {
-                let rewards = self.client.get_block(block)?.rewards;
+                let rewards = self.get_block(block, true)?.rewards.unwrap_or_default();
                 self.cache.add_epoch_rewards(epoch, &rewards)?;
+                self.gauges
+                    .cached_reward_epochs
+                    .set(self.cache.epoch_rewards_count() as i64);
                 Ok(Some(rewards))
             })
         }
     }
+
+    /// Gets the block in `slot` without transactions, with its rewards iff `rewards` is `true`.
+    fn get_block(&self, slot: u64, rewards: bool) -> Result<UiConfirmedBlock, ClientError> {
+        retry_with_backoff(
+            || {
+                self.gauges.count_rpc_error(
+                    "get_block",
+                    self.client
+                        .get_block_with_config(slot, block_config(self.client, rewards)),
+                )
+            },
+            self.max_retries,
+        )
+    }
 }
 
-/// Calculates the staking APY of an `AccountInfo` containing a `StakeState`.
+/// Calculates the staking APY of a stake account in `stake_state`.
 /// Returns the calculated APY while registering the delegated voter in `seen_voters`
 /// for later reference.
 fn calculate_staking_apy(
-    account_info: &Account,
+    stake_state: &StakeState,
     seen_voters: &mut BTreeSet<Pubkey>,
     epoch_duration: f64,
     lamports: u64,
     post_balance: u64,
 ) -> anyhow::Result<Option<StakingApy>> {
-    let stake_state: StakeState = bincode::deserialize(&account_info.data)?;
     if let Some(delegation) = stake_state.delegation() {
         let percent = if !seen_voters.contains(&delegation.voter_pubkey) && lamports > 0 {
             let prev_balance = post_balance - lamports;
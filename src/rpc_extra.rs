@@ -1,22 +1,127 @@
 use crate::config::Whitelist;
+use log::{debug, warn};
+use semver::Version;
+use solana_client::client_error::{ClientError, ClientErrorKind};
+use solana_client::rpc_config::RpcBlockConfig;
 use solana_client::{rpc_client::RpcClient, rpc_response::RpcVoteAccountStatus};
 use solana_sdk::clock::Epoch;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::epoch_info::EpochInfo;
+use solana_transaction_status::{TransactionDetails, UiTransactionEncoding};
+use std::thread::sleep;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-/// Applies `f` to the first block in `epoch`.
+/// Delay before the first retry of a failed RPC call, in milliseconds. Doubled on every retry.
+const RETRY_BASE_DELAY_MS: u64 = 250;
+
+/// Maximum number of blocks requested when searching for the first block of an epoch.
+const FIRST_BLOCK_MAX_SEARCH_LIMIT: usize = 1_000;
+
+/// Minimum number of elapsed slots in the current epoch for its average slot time to be
+/// extrapolated. Fewer slots give an unreliable average, or none at all at slot index 0.
+pub const MIN_EXTRAPOLATION_SLOTS: u64 = 10;
+
+/// HTTP status code of a rate limited request.
+const HTTP_TOO_MANY_REQUESTS: u16 = 429;
+
+/// Maximum number of times the retry delay is doubled.
+const RETRY_MAX_DOUBLINGS: u32 = 8;
+
+/// Calls `f` until it succeeds, retrying at most `max_retries` times with exponential backoff and
+/// jitter. Only transient errors are retried, since an error response of the RPC node, such as for
+/// a skipped slot, would only be returned again. Returns the last error if all retries fail.
+pub fn retry_with_backoff<T, F>(f: F, max_retries: u32) -> Result<T, ClientError>
+where
+    F: Fn() -> Result<T, ClientError>,
+{
+    let mut retries = 0;
+    loop {
+        match f() {
+            Ok(value) => return Ok(value),
+            Err(e) if retries < max_retries && is_transient(&e) => {
+                // Cheap jitter without a random number generator.
+                let jitter = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.subsec_nanos() as u64 % RETRY_BASE_DELAY_MS)
+                    .unwrap_or_default();
+                let delay = Duration::from_millis(
+                    (RETRY_BASE_DELAY_MS << retries.min(RETRY_MAX_DOUBLINGS)) + jitter,
+                );
+                retries += 1;
+                warn!(
+                    "RPC call failed, retry {} of {} in {:?}: {}",
+                    retries, max_retries, delay, e
+                );
+                sleep(delay);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Returns `true` iff `error` is a connection or IO error, or an HTTP error status that a retry may
+/// not run into again, namely a server error or too many requests.
+fn is_transient(error: &ClientError) -> bool {
+    match error.kind() {
+        ClientErrorKind::Io(_) => true,
+        ClientErrorKind::Reqwest(e) => e.status().map_or(true, |status| {
+            status.is_server_error() || status.as_u16() == HTTP_TOO_MANY_REQUESTS
+        }),
+        _ => false,
+    }
+}
+
+/// Returns the configuration for fetching a block without transactions at the commitment level of
+/// `client`. Rewards are included iff `rewards` is `true`.
+pub fn block_config(client: &RpcClient, rewards: bool) -> RpcBlockConfig {
+    // Blocks cannot be fetched at the `processed` commitment level.
+    let commitment = if client.commitment().is_at_least_confirmed() {
+        client.commitment()
+    } else {
+        CommitmentConfig::confirmed()
+    };
+
+    RpcBlockConfig {
+        encoding: Some(UiTransactionEncoding::Base64),
+        transaction_details: Some(TransactionDetails::None),
+        rewards: Some(rewards),
+        commitment: Some(commitment),
+    }
+}
+
+/// Applies `f` to the first block in `epoch`. Returns `Ok(None)` without calling `f` if `epoch` has
+/// no confirmed block yet.
+///
+/// The first slots of an epoch may be skipped, in which case the first block is at a later slot.
+/// Some RPC nodes return no blocks for a small limit if the initial skip streak is long, so the
+/// search is retried with increasing limits, up to `FIRST_BLOCK_MAX_SEARCH_LIMIT` blocks.
 pub fn with_first_block<F, A>(client: &RpcClient, epoch: Epoch, f: F) -> anyhow::Result<Option<A>>
 where
     F: Fn(u64) -> anyhow::Result<Option<A>>,
 {
     let epoch_schedule = client.get_epoch_schedule()?;
     let first_slot = epoch_schedule.get_first_slot_in_epoch(epoch);
+    let last_slot = epoch_schedule.get_last_slot_in_epoch(epoch);
 
     // First block in `epoch`.
-    let first_block = client.get_blocks_with_limit(first_slot, 1)?.get(0).cloned();
+    let mut limit = 1;
+    let first_block = loop {
+        if let Some(block) = client.get_blocks_with_limit(first_slot, limit)?.first() {
+            break Some(*block);
+        }
+        if limit >= FIRST_BLOCK_MAX_SEARCH_LIMIT {
+            break None;
+        }
+        debug!(
+            "No block found in the first {} blocks from slot {}, widening the search",
+            limit, first_slot
+        );
+        limit = (limit * 10).min(FIRST_BLOCK_MAX_SEARCH_LIMIT);
+    };
 
-    if let Some(block) = first_block {
-        f(block)
-    } else {
-        Ok(None)
+    match first_block {
+        Some(block) if block <= last_slot => f(block),
+        _ => Ok(None),
     }
 }
 
@@ -36,3 +141,45 @@ pub fn node_pubkeys(vote_pubkeys: &Whitelist, vote_accounts: &RpcVoteAccountStat
         )
     }
 }
+
+/// Returns `true` iff `epoch_info` looks like a placeholder returned by an RPC node that is still
+/// starting up rather than actual cluster state.
+pub fn is_empty_epoch_info(epoch_info: &EpochInfo) -> bool {
+    epoch_info.slots_in_epoch == 0 || epoch_info.absolute_slot == 0
+}
+
+/// Returns `true` iff `vote_accounts` contains neither current nor delinquent vote accounts, which
+/// only happens while the RPC node is starting up.
+pub fn is_empty_vote_accounts(vote_accounts: &RpcVoteAccountStatus) -> bool {
+    vote_accounts.current.is_empty() && vote_accounts.delinquent.is_empty()
+}
+
+/// Infers the validator client software from the gossip `version` of a node. Firedancer reports
+/// versions with major version 0, Agave (including Jito) reports 2 and higher, and the Solana Labs
+/// client reports 1. Gossip does not distinguish Jito from Agave.
+pub fn client_name(version: Option<&str>) -> &'static str {
+    match version
+        .and_then(|v| Version::parse(v).ok())
+        .map(|v| v.major)
+    {
+        Some(0) => "firedancer",
+        Some(1) => "solana-labs",
+        Some(_) => "agave",
+        None => "unknown",
+    }
+}
+
+/// Returns a short name of the kind of `error` for use as a metric label.
+pub fn error_kind(error: &ClientError) -> &'static str {
+    match error.kind() {
+        ClientErrorKind::Io(_) => "io",
+        ClientErrorKind::Reqwest(e) if e.is_timeout() => "timeout",
+        ClientErrorKind::Reqwest(_) => "http",
+        ClientErrorKind::RpcError(_) => "rpc",
+        ClientErrorKind::SerdeJson(_) => "deserialization",
+        ClientErrorKind::SigningError(_) => "signing",
+        ClientErrorKind::TransactionError(_) => "transaction",
+        ClientErrorKind::FaucetError(_) => "faucet",
+        ClientErrorKind::Custom(_) => "custom",
+    }
+}
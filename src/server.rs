@@ -0,0 +1,131 @@
+//! HTTP(S) server of the Prometheus metrics endpoint.
+
+use anyhow::{anyhow, Context};
+use log::{debug, error};
+use prometheus_exporter::prometheus::{self, Encoder, TextEncoder};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use subtle::ConstantTimeEq;
+use tiny_http::{Header, Request, Response, Server, SslConfig};
+
+/// Path of the metrics endpoint.
+pub const METRICS_PATH: &str = "/metrics";
+
+/// Serves the metrics of the default Prometheus registry.
+pub struct MetricsServer {
+    /// Held while metrics are updated so that scrapes never see partially updated metrics.
+    update_lock: Arc<Mutex<()>>,
+}
+
+impl MetricsServer {
+    /// Starts serving `METRICS_PATH` on `target` in a separate thread. Metrics are served over
+    /// HTTPS if `tls` is given a certificate and private key path, and over HTTP otherwise. If
+    /// `credentials` are given a username and password, requests must authenticate with them
+    /// using HTTP basic auth.
+    pub fn start(
+        target: SocketAddr,
+        tls: Option<(&Path, &Path)>,
+        credentials: Option<(&str, &str)>,
+    ) -> anyhow::Result<Self> {
+        let server = match tls {
+            Some((cert_path, key_path)) => Server::https(
+                target,
+                SslConfig {
+                    certificate: fs::read(cert_path).with_context(|| {
+                        format!("Could not read TLS certificate {:?}", cert_path)
+                    })?,
+                    private_key: fs::read(key_path).with_context(|| {
+                        format!("Could not read TLS private key {:?}", key_path)
+                    })?,
+                },
+            ),
+            None => Server::http(target),
+        }
+        .map_err(|e| anyhow!("Could not bind the metrics endpoint to {}: {}", target, e))?;
+        debug!("Serving {} on {}", METRICS_PATH, target);
+
+        let authorization = credentials
+            .map(|(username, password)| base64::encode(format!("{}:{}", username, password)));
+        let update_lock = Arc::new(Mutex::new(()));
+        let server_lock = update_lock.clone();
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                if let Err(e) = respond(request, &server_lock, authorization.as_deref()) {
+                    error!("Could not respond to metrics request: {:?}", e);
+                }
+            }
+        });
+
+        Ok(Self { update_lock })
+    }
+
+    /// Blocks scrapes until the returned guard is dropped.
+    pub fn lock_updates(&self) -> MutexGuard<'_, ()> {
+        self.update_lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Responds to `request` with the gathered metrics if it carries the expected basic auth
+/// `authorization` token, if any.
+fn respond(
+    request: Request,
+    update_lock: &Mutex<()>,
+    authorization: Option<&str>,
+) -> anyhow::Result<()> {
+    // The query string, if any, does not affect the response.
+    if request.url().split('?').next() != Some(METRICS_PATH) {
+        request.respond(Response::from_string("not found").with_status_code(404))?;
+        return Ok(());
+    }
+
+    if let Some(authorization) = authorization {
+        let authorized = request
+            .headers()
+            .iter()
+            .filter(|h| h.field.equiv("Authorization"))
+            .any(|h| is_authorized(h.value.as_str(), authorization));
+        if !authorized {
+            let challenge = Header::from_bytes(
+                &b"WWW-Authenticate"[..],
+                &b"Basic realm=\"solana-exporter\""[..],
+            )
+            .map_err(|_| anyhow!("Invalid authentication challenge header"))?;
+            request.respond(
+                Response::from_string("unauthorized")
+                    .with_status_code(401)
+                    .with_header(challenge),
+            )?;
+            return Ok(());
+        }
+    }
+
+    let encoder = TextEncoder::new();
+    let mut body = vec![];
+    {
+        let _guard = update_lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        encoder.encode(&prometheus::gather(), &mut body)?;
+    }
+    let content_type = Header::from_bytes(&b"Content-Type"[..], encoder.format_type().as_bytes())
+        .map_err(|_| anyhow!("Invalid content type header"))?;
+    request.respond(Response::from_data(body).with_header(content_type))?;
+    Ok(())
+}
+
+/// Returns `true` iff the `Authorization` header value `header` uses the basic auth scheme with
+/// `token`. The token is compared in constant time so that its value cannot be guessed from the
+/// response time.
+fn is_authorized(header: &str, token: &str) -> bool {
+    match header.trim().split_once(' ') {
+        Some((scheme, credentials)) if scheme.eq_ignore_ascii_case("Basic") => {
+            credentials.trim().as_bytes().ct_eq(token.as_bytes()).into()
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,59 @@
+//! Shadow metrics: copies of existing metrics exported under a different name, so that dashboards
+//! can be migrated gradually when metric names change.
+
+use prometheus_exporter::prometheus::core::{Collector, Desc};
+use prometheus_exporter::prometheus::proto::MetricFamily;
+
+/// A collector that re-exports the metrics of another collector under a different name.
+pub struct ShadowCollector {
+    /// The collector whose metrics are shadowed.
+    source: Box<dyn Collector>,
+    /// The name under which the metrics are exported.
+    name: String,
+    /// Descriptors of the shadowed metrics under the new name.
+    descs: Vec<Desc>,
+}
+
+impl ShadowCollector {
+    /// Makes a collector exporting the metrics of `source` under `name`.
+    pub fn new(source: Box<dyn Collector>, name: &str) -> anyhow::Result<Self> {
+        let descs = source
+            .desc()
+            .into_iter()
+            .map(|desc| {
+                Desc::new(
+                    name.to_string(),
+                    desc.help.clone(),
+                    desc.variable_labels.clone(),
+                    desc.const_label_pairs
+                        .iter()
+                        .map(|pair| (pair.get_name().to_string(), pair.get_value().to_string()))
+                        .collect(),
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            source,
+            name: name.to_string(),
+            descs,
+        })
+    }
+}
+
+impl Collector for ShadowCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.source
+            .collect()
+            .into_iter()
+            .map(|mut family| {
+                family.set_name(self.name.clone());
+                family
+            })
+            .collect()
+    }
+}
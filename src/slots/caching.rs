@@ -0,0 +1,204 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use solana_sdk::clock::Epoch;
+use std::collections::{BTreeMap, VecDeque};
+
+/// Name of the skipped slots caching tree.
+pub const SKIPPED_SLOTS_TREE_NAME: &str = "skipped_slots";
+
+/// Name of the tree of recent slot outcomes per leader.
+pub const RECENT_SLOTS_TREE_NAME: &str = "skipped_slots_recent";
+
+/// Name of the tree of inverted leader schedules by epoch.
+pub const LEADER_SCHEDULE_TREE_NAME: &str = "leader_schedule";
+
+/// Name of the tree of leader slot counts by epoch.
+pub const LEADER_SLOT_COUNTS_TREE_NAME: &str = "leader_slot_counts";
+
+/// Name of the tree of the skipped and validated slots of each leader in the current epoch.
+pub const EPOCH_SLOT_COUNTS_TREE_NAME: &str = "epoch_slot_counts";
+
+/// Key under which the scan progress is stored.
+const SCAN_PROGRESS_KEY: &str = "scan_progress";
+
+/// How far the skipped slots have been scanned.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct ScanProgress {
+    /// The last scanned epoch.
+    pub epoch: Epoch,
+    /// The slot index in `epoch` up to which slots have been scanned.
+    pub slot_index: u64,
+}
+
+/// Values of the leader slots counter by pubkey label and status label.
+pub type LeaderSlotCounts = BTreeMap<(String, String), u64>;
+
+/// Numbers of skipped and validated leader slots of a leader.
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SlotCounts {
+    pub skipped: u64,
+    pub validated: u64,
+}
+
+impl SlotCounts {
+    /// Returns the number of leader slots, skipped or validated.
+    pub fn total(&self) -> u64 {
+        self.skipped + self.validated
+    }
+}
+
+/// Slot counts in an epoch by leader pubkey.
+pub type EpochSlotCounts = BTreeMap<String, SlotCounts>;
+
+/// A caching database for the state of the skipped slots monitor.
+pub struct SkippedSlotsCache {
+    tree: sled::Tree,
+    recent_slots: sled::Tree,
+    leader_schedules: sled::Tree,
+    leader_slot_counts: sled::Tree,
+    epoch_slot_counts: sled::Tree,
+}
+
+impl SkippedSlotsCache {
+    /// Creates a new cache using trees.
+    pub fn new(
+        tree: sled::Tree,
+        recent_slots: sled::Tree,
+        leader_schedules: sled::Tree,
+        leader_slot_counts: sled::Tree,
+        epoch_slot_counts: sled::Tree,
+    ) -> Self {
+        Self {
+            tree,
+            recent_slots,
+            leader_schedules,
+            leader_slot_counts,
+            epoch_slot_counts,
+        }
+    }
+
+    /// Sets the scan progress.
+    pub fn set_scan_progress(&self, progress: &ScanProgress) -> anyhow::Result<()> {
+        self.tree
+            .insert(SCAN_PROGRESS_KEY, bincode::serialize(progress)?)
+            .context("could not insert scan progress into database")?;
+        Ok(())
+    }
+
+    /// Returns the scan progress, if any was stored.
+    pub fn get_scan_progress(&self) -> anyhow::Result<Option<ScanProgress>> {
+        self.tree
+            .get(SCAN_PROGRESS_KEY)
+            .context("could not fetch scan progress from database")?
+            .map(|x| bincode::deserialize(&x))
+            .transpose()
+            .context("could not deserialize fetched scan progress")
+    }
+
+    /// Sets the outcomes of the most recent leader slots of `leader`, oldest first. `true` means
+    /// that the slot was skipped.
+    pub fn set_recent_slots(&self, leader: &str, skipped: &VecDeque<bool>) -> anyhow::Result<()> {
+        self.recent_slots
+            .insert(leader, bincode::serialize(skipped)?)
+            .context("could not insert recent slots into database")?;
+        Ok(())
+    }
+
+    /// Returns the outcomes of the most recent leader slots of `leader`, oldest first, or an empty
+    /// buffer if none were stored.
+    pub fn get_recent_slots(&self, leader: &str) -> anyhow::Result<VecDeque<bool>> {
+        Ok(self
+            .recent_slots
+            .get(leader)
+            .context("could not fetch recent slots from database")?
+            .map(|x| bincode::deserialize(&x))
+            .transpose()
+            .context("could not deserialize fetched recent slots")?
+            .unwrap_or_default())
+    }
+
+    /// Sets the slot leaders of `epoch` by slot index, replacing those of any other epoch since only
+    /// the current epoch is ever needed.
+    pub fn set_slot_leaders(
+        &self,
+        epoch: Epoch,
+        slot_leaders: &BTreeMap<usize, String>,
+    ) -> anyhow::Result<()> {
+        self.leader_schedules
+            .clear()
+            .context("could not clear leader schedules in database")?;
+        self.leader_schedules
+            .insert(epoch.to_be_bytes(), bincode::serialize(slot_leaders)?)
+            .context("could not insert leader schedule into database")?;
+        Ok(())
+    }
+
+    /// Returns the slot leaders of `epoch` by slot index, if they were stored.
+    pub fn get_slot_leaders(
+        &self,
+        epoch: Epoch,
+    ) -> anyhow::Result<Option<BTreeMap<usize, String>>> {
+        self.leader_schedules
+            .get(epoch.to_be_bytes())
+            .context("could not fetch leader schedule from database")?
+            .map(|x| bincode::deserialize(&x))
+            .transpose()
+            .context("could not deserialize fetched leader schedule")
+    }
+
+    /// Sets the leader slot counts as of the scan progress in `epoch`, pruning those of earlier
+    /// epochs.
+    pub fn set_leader_slot_counts(
+        &self,
+        epoch: Epoch,
+        counts: &LeaderSlotCounts,
+    ) -> anyhow::Result<()> {
+        self.leader_slot_counts
+            .insert(epoch.to_be_bytes(), bincode::serialize(counts)?)
+            .context("could not insert leader slot counts into database")?;
+        // Keys are big-endian epochs, hence ordered by epoch.
+        for kv in self.leader_slot_counts.range(..epoch.to_be_bytes()) {
+            let (k, _) = kv?;
+            self.leader_slot_counts
+                .remove(k)
+                .context("could not remove leader slot counts from database")?;
+        }
+        Ok(())
+    }
+
+    /// Returns the leader slot counts as of the scan progress in `epoch`, if they were stored.
+    pub fn get_leader_slot_counts(&self, epoch: Epoch) -> anyhow::Result<Option<LeaderSlotCounts>> {
+        self.leader_slot_counts
+            .get(epoch.to_be_bytes())
+            .context("could not fetch leader slot counts from database")?
+            .map(|x| bincode::deserialize(&x))
+            .transpose()
+            .context("could not deserialize fetched leader slot counts")
+    }
+
+    /// Sets the slot counts of `epoch` as of the scan progress, replacing those of any other epoch
+    /// since only the current epoch is ever needed.
+    pub fn set_epoch_slot_counts(
+        &self,
+        epoch: Epoch,
+        counts: &EpochSlotCounts,
+    ) -> anyhow::Result<()> {
+        self.epoch_slot_counts
+            .clear()
+            .context("could not clear epoch slot counts in database")?;
+        self.epoch_slot_counts
+            .insert(epoch.to_be_bytes(), bincode::serialize(counts)?)
+            .context("could not insert epoch slot counts into database")?;
+        Ok(())
+    }
+
+    /// Returns the slot counts of `epoch` as of the scan progress, if they were stored.
+    pub fn get_epoch_slot_counts(&self, epoch: Epoch) -> anyhow::Result<Option<EpochSlotCounts>> {
+        self.epoch_slot_counts
+            .get(epoch.to_be_bytes())
+            .context("could not fetch epoch slot counts from database")?
+            .map(|x| bincode::deserialize(&x))
+            .transpose()
+            .context("could not deserialize fetched epoch slot counts")
+    }
+}
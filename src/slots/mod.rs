@@ -0,0 +1,522 @@
+//! Statistics of skipped and validated slots.
+
+use crate::config::Whitelist;
+use crate::gauges::{PrometheusGauges, PUBKEY_LABEL, STATUS_LABEL};
+use crate::rpc_extra::retry_with_backoff;
+use crate::slots::caching::{EpochSlotCounts, LeaderSlotCounts, ScanProgress, SkippedSlotsCache};
+use log::{debug, log_enabled, Level};
+use prometheus_exporter::prometheus::core::Collector;
+use solana_client::{client_error::ClientError, rpc_client::RpcClient};
+use solana_sdk::epoch_info::EpochInfo;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+
+pub mod caching;
+
+/// Number of blocks to fetch per request.
+const SLOT_GET_BLOCK_STEP: usize = 1_000;
+
+/// The monitor of skipped and validated slots per validator with minimal internal state.
+pub struct SkippedSlotsMonitor<'a> {
+    /// Shared Solana RPC client.
+    client: &'a RpcClient,
+    /// Prometheus gauges.
+    gauges: &'a PrometheusGauges,
+    /// Caching database for the monitor state.
+    cache: &'a SkippedSlotsCache,
+    /// The last observed epoch number.
+    epoch_number: u64,
+    /// The last observed slot index.
+    slot_index: u64,
+    /// The slot leader schedule for the last observed epoch.
+    slot_leaders: BTreeMap<usize, String>,
+    /// The leaders in `slot_leaders` that are in the node whitelist.
+    whitelisted_leaders: HashSet<String>,
+    /// Skipped and validated slots of each leader in the last observed epoch, as far as it has been
+    /// scanned.
+    epoch_slot_counts: EpochSlotCounts,
+    /// `true` iff `SkippedSlotMonitor::export_skipped_slots` already ran.
+    already_ran: bool,
+    /// `true` iff the scan progress was restored from the cache and the slot leader schedule has not
+    /// been fetched yet.
+    resumed: bool,
+    /// Number of most recent leader slots per validator over which the rolling skip rate is
+    /// calculated, or `None` to not calculate it.
+    rolling_window: Option<usize>,
+    /// Maximum number of retries of a failed RPC call.
+    max_retries: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum SlotStatus {
+    Skipped,
+    Validated,
+}
+
+impl Display for SlotStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            SlotStatus::Skipped => "skipped",
+            SlotStatus::Validated => "validated",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl<'a> SkippedSlotsMonitor<'a> {
+    /// Constructs a monitor given `client`. The scan progress and the leader slot counts are restored
+    /// from `cache` if available so that a restart neither rescans the whole epoch nor resets the
+    /// counters.
+    pub fn new(
+        client: &'a RpcClient,
+        gauges: &'a PrometheusGauges,
+        cache: &'a SkippedSlotsCache,
+        rolling_window: Option<usize>,
+        max_retries: u32,
+    ) -> anyhow::Result<Self> {
+        let progress = cache.get_scan_progress()?;
+        let mut epoch_slot_counts = EpochSlotCounts::new();
+        if let Some(progress) = progress {
+            epoch_slot_counts = cache
+                .get_epoch_slot_counts(progress.epoch)?
+                .unwrap_or_default();
+            debug!("Restored skipped slots scan progress {:?}", progress);
+            if let Some(counts) = cache.get_leader_slot_counts(progress.epoch)? {
+                for ((label, status), count) in &counts {
+                    gauges
+                        .leader_slots
+                        .with_label_values(&[label.as_str(), status.as_str()])
+                        .inc_by(*count);
+                }
+                debug!("Restored {} leader slot counts", counts.len());
+            }
+        }
+
+        Ok(Self {
+            client,
+            gauges,
+            cache,
+            epoch_number: progress.map(|p| p.epoch).unwrap_or_default(),
+            slot_index: progress.map(|p| p.slot_index).unwrap_or_default(),
+            slot_leaders: Default::default(),
+            whitelisted_leaders: Default::default(),
+            epoch_slot_counts,
+            already_ran: progress.is_some(),
+            resumed: progress.is_some(),
+            rolling_window,
+            max_retries,
+        })
+    }
+
+    /// Exports the skipped slot statistics given `epoch_info`.
+    pub fn export_skipped_slots(
+        &mut self,
+        epoch_info: &EpochInfo,
+        node_whitelist: &Whitelist,
+    ) -> anyhow::Result<()> {
+        if self.epoch_number != epoch_info.epoch {
+            if self.resumed {
+                // The epoch changed while the exporter was down. Rescan the current epoch in full.
+                self.already_ran = false;
+                self.resumed = false;
+            }
+            // Update the monitor state.
+            self.load_slot_leaders(epoch_info.epoch, node_whitelist)?;
+            self.export_leader_slot_gaps()?;
+            self.export_scheduled_leader_slots()?;
+            self.epoch_number = epoch_info.epoch;
+            // The per-epoch block counts start over with the first slot of the new epoch, and
+            // leaders without slots in it are removed.
+            self.slot_index = 0;
+            self.epoch_slot_counts.clear();
+            self.gauges.blocks_produced.reset();
+            self.gauges.blocks_expected.reset();
+            self.gauges.skip_rate_rank.reset();
+            debug!("SkippedSlotsMonitor state updated");
+        } else if self.resumed {
+            // Resume from the restored slot index once the leader schedule is known.
+            self.load_slot_leaders(epoch_info.epoch, node_whitelist)?;
+            self.export_leader_slot_gaps()?;
+            self.export_scheduled_leader_slots()?;
+            self.resumed = false;
+            debug!(
+                "SkippedSlotsMonitor resumed at slot index {}",
+                self.slot_index
+            );
+        } else if self.slot_index == epoch_info.slot_index {
+            debug!("At the slot index");
+            return Ok(());
+        }
+
+        let first_slot = epoch_info.absolute_slot - epoch_info.slot_index;
+        let (abs_range_start, range_start) = if self.already_ran {
+            // Start from the last seen slot if already ran before.
+            (first_slot + self.slot_index, self.slot_index)
+        } else {
+            // Start from the first slot in the current epoch if running for the first time.
+            self.already_ran = true;
+            (first_slot, 0)
+        };
+        let range_end = epoch_info.slot_index;
+        // A lagging or load-balanced RPC node may report an earlier slot than the previous pass.
+        if range_end < range_start {
+            debug!(
+                "Slot index {} is behind the scanned slot index {}",
+                range_end, range_start
+            );
+            return Ok(());
+        }
+        let abs_range_end = first_slot + range_end;
+        let scanned_slots = range_end - range_start;
+        self.gauges
+            .skipped_slots_scanned_total
+            .inc_by(scanned_slots);
+        self.gauges
+            .skipped_slots_last_scan
+            .set(scanned_slots as i64);
+
+        let mut confirmed_blocks = vec![];
+        for abs_range_step in (abs_range_start..abs_range_end).step_by(SLOT_GET_BLOCK_STEP) {
+            let abs_range_step_end =
+                abs_range_end.min(abs_range_step + SLOT_GET_BLOCK_STEP as u64 - 1);
+            debug!(
+                "Getting confirmed blocks from {} to {}",
+                abs_range_step, abs_range_step_end
+            );
+            confirmed_blocks.extend(retry_with_backoff(
+                || {
+                    self.gauges.count_rpc_error(
+                        "get_blocks",
+                        self.client
+                            .get_blocks(abs_range_step, Some(abs_range_step_end)),
+                    )
+                },
+                self.max_retries,
+            )?);
+        }
+        confirmed_blocks.sort_unstable();
+        debug!(
+            "Confirmed blocks from {} to {}: {:?}",
+            abs_range_start, abs_range_end, confirmed_blocks
+        );
+        let mut feed = self.gauges.leader_slots.local();
+        // Outcomes of the scanned slots per leader in slot order. `true` means skipped.
+        let mut scanned_outcomes: HashMap<&str, Vec<bool>> = HashMap::new();
+        for slot_in_epoch in range_start..range_end {
+            let leader = if let Some(leader) = self.slot_leaders.get(&(slot_in_epoch as usize)) {
+                leader
+            } else {
+                continue;
+            };
+
+            let absolute_slot = first_slot + slot_in_epoch;
+            let status = if confirmed_blocks.binary_search(&absolute_slot).is_ok() {
+                SlotStatus::Validated
+            } else {
+                SlotStatus::Skipped
+            };
+            // Every leader is counted in the epoch so that whitelisted leaders can be ranked among
+            // all of them.
+            let counts = self.epoch_slot_counts.entry(leader.clone()).or_default();
+            match status {
+                SlotStatus::Skipped => counts.skipped += 1,
+                SlotStatus::Validated => counts.validated += 1,
+            }
+            if !self.whitelisted_leaders.contains(leader) {
+                continue;
+            }
+            if log_enabled!(Level::Debug)
+                && (slot_in_epoch < range_start + 50 || range_end - 50 < slot_in_epoch)
+            {
+                // Log only a subset of slots on the first run.
+                debug!("Leader {} {} slot {}", leader, status, absolute_slot);
+            }
+            feed.with_label_values(&[
+                &self.gauges.pubkey_labels.label(leader),
+                &status.to_string(),
+            ])
+            .inc();
+            scanned_outcomes
+                .entry(leader.as_str())
+                .or_default()
+                .push(status == SlotStatus::Skipped);
+        }
+        feed.flush();
+
+        if let Some(window) = self.rolling_window {
+            self.export_rolling_skip_rate(scanned_outcomes, window)?;
+        }
+
+        // Update skipped slot percentages.
+        for slot_in_epoch in range_start..range_end {
+            let leader = if let Some(leader) = self.whitelisted_leader(slot_in_epoch) {
+                leader
+            } else {
+                continue;
+            };
+            let label = self.gauges.pubkey_labels.label(leader);
+            let get_count = |slot_status: SlotStatus| {
+                self.gauges
+                    .leader_slots
+                    .get_metric_with_label_values(&[&label, &slot_status.to_string()])
+                    .map(|m| m.get())
+                    .unwrap_or_default()
+            };
+            let skipped_count = get_count(SlotStatus::Skipped);
+            let validated_count = get_count(SlotStatus::Validated);
+            // total_count > 0 since either skipped_count > 0 or validated_count > 0. Hence the
+            // result of division by total_count is always defined.
+            let total_count = validated_count + skipped_count;
+            assert!(total_count > 0);
+            let skipped_percent = (skipped_count as f64 / total_count as f64) * 100.0;
+            let epoch_counts = self
+                .epoch_slot_counts
+                .get(leader)
+                .copied()
+                .unwrap_or_default();
+            self.gauges
+                .skipped_slot_percent
+                .get_metric_with_label_values(&[&label])
+                .map(|c| c.set(skipped_percent as f64))?;
+            self.gauges
+                .blocks_produced
+                .get_metric_with_label_values(&[&label])
+                .map(|c| c.set(epoch_counts.validated as i64))?;
+            self.gauges
+                .blocks_expected
+                .get_metric_with_label_values(&[&label])
+                .map(|c| c.set(epoch_counts.total() as i64))?;
+        }
+
+        // Skipped slot percentage over all leaders in the current epoch.
+        let (skipped_count, total_count) = self
+            .epoch_slot_counts
+            .values()
+            .fold((0, 0), |(skipped, total), counts| {
+                (skipped + counts.skipped, total + counts.total())
+            });
+        if total_count > 0 {
+            self.gauges
+                .cluster_skipped_slot_percent
+                .set((skipped_count as f64 / total_count as f64) * 100.0);
+        }
+
+        self.export_skip_rate_ranks()?;
+
+        self.slot_index = epoch_info.slot_index;
+        self.cache
+            .set_leader_slot_counts(self.epoch_number, &self.leader_slot_counts())?;
+        self.cache
+            .set_epoch_slot_counts(self.epoch_number, &self.epoch_slot_counts)?;
+        self.cache.set_scan_progress(&ScanProgress {
+            epoch: self.epoch_number,
+            slot_index: self.slot_index,
+        })?;
+        debug!("Exported leader slots and updated the slot index");
+        Ok(())
+    }
+
+    /// Returns the current values of the leader slots counter.
+    fn leader_slot_counts(&self) -> LeaderSlotCounts {
+        self.gauges
+            .leader_slots
+            .collect()
+            .iter()
+            .flat_map(|family| family.get_metric())
+            .filter_map(|metric| {
+                let labels = metric.get_label();
+                let label_value = |name| {
+                    labels
+                        .iter()
+                        .find(|label| label.get_name() == name)
+                        .map(|label| label.get_value().to_string())
+                };
+                Some((
+                    (label_value(PUBKEY_LABEL)?, label_value(STATUS_LABEL)?),
+                    metric.get_counter().get_value() as u64,
+                ))
+            })
+            .collect()
+    }
+
+    /// Exports the percentile rank of the skip rate of each whitelisted leader among all leaders
+    /// with elapsed slots in the current epoch.
+    fn export_skip_rate_ranks(&self) -> anyhow::Result<()> {
+        let skip_rates: HashMap<&str, f64> = self
+            .epoch_slot_counts
+            .iter()
+            .filter(|(_, counts)| counts.total() > 0)
+            .map(|(leader, counts)| {
+                (
+                    leader.as_str(),
+                    counts.skipped as f64 / counts.total() as f64,
+                )
+            })
+            .collect();
+
+        let mut sorted_rates: Vec<f64> = skip_rates.values().copied().collect();
+        sorted_rates.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+        for (leader, rate) in skip_rates {
+            if !self.whitelisted_leaders.contains(leader) {
+                continue;
+            }
+            let at_most = sorted_rates.partition_point(|r| *r <= rate);
+            self.gauges
+                .skip_rate_rank
+                .get_metric_with_label_values(&[&self.gauges.pubkey_labels.label(leader)])
+                .map(|c| c.set(at_most as f64 / sorted_rates.len() as f64 * 100.0))?;
+        }
+        Ok(())
+    }
+
+    /// Exports the average and maximum number of slots between consecutive leader rotations of each
+    /// leader in the current epoch. Consecutive leader slots form a single rotation. Leaders of the
+    /// previous epoch without gaps in the current epoch are removed.
+    fn export_leader_slot_gaps(&self) -> anyhow::Result<()> {
+        self.gauges.leader_slot_gap_avg.reset();
+        self.gauges.leader_slot_gap_max.reset();
+
+        // Slots are iterated in order, so the last slot of each leader is the one seen before.
+        let mut last_slots: HashMap<&str, usize> = HashMap::new();
+        let mut gaps: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (slot, leader) in self.whitelisted_slot_leaders() {
+            if let Some(last_slot) = last_slots.insert(leader, *slot) {
+                if slot - last_slot > 1 {
+                    gaps.entry(leader).or_default().push(slot - last_slot - 1);
+                }
+            }
+        }
+
+        for (leader, gaps) in gaps {
+            let label = self.gauges.pubkey_labels.label(leader);
+            let avg = gaps.iter().sum::<usize>() as f64 / gaps.len() as f64;
+            let max = gaps.iter().max().copied().unwrap_or_default();
+            self.gauges
+                .leader_slot_gap_avg
+                .get_metric_with_label_values(&[&label])
+                .map(|c| c.set(avg))?;
+            self.gauges
+                .leader_slot_gap_max
+                .get_metric_with_label_values(&[&label])
+                .map(|c| c.set(max as i64))?;
+        }
+        Ok(())
+    }
+
+    /// Exports the number of leader slots of each leader in the current epoch. Leaders of the
+    /// previous epoch that have no slots in the current epoch are removed.
+    fn export_scheduled_leader_slots(&self) -> anyhow::Result<()> {
+        let mut counts: HashMap<&str, i64> = HashMap::new();
+        for (_, leader) in self.whitelisted_slot_leaders() {
+            *counts.entry(leader).or_default() += 1;
+        }
+
+        self.gauges.leader_slots_scheduled.reset();
+        for (leader, count) in counts {
+            self.gauges
+                .leader_slots_scheduled
+                .get_metric_with_label_values(&[&self.gauges.pubkey_labels.label(leader)])
+                .map(|c| c.set(count))?;
+        }
+        Ok(())
+    }
+
+    /// Appends `scanned_outcomes` to the recent slot outcomes of each leader, keeping at most
+    /// `window` of them, and exports the skip rate over those.
+    fn export_rolling_skip_rate(
+        &self,
+        scanned_outcomes: HashMap<&str, Vec<bool>>,
+        window: usize,
+    ) -> anyhow::Result<()> {
+        for (leader, outcomes) in scanned_outcomes {
+            let mut recent = self.cache.get_recent_slots(leader)?;
+            recent.extend(outcomes);
+            while recent.len() > window {
+                recent.pop_front();
+            }
+            self.cache.set_recent_slots(leader, &recent)?;
+
+            if recent.is_empty() {
+                continue;
+            }
+            let skipped_count = recent.iter().filter(|skipped| **skipped).count();
+            let skip_rate = (skipped_count as f64 / recent.len() as f64) * 100.0;
+            self.gauges
+                .skip_rate_rolling
+                .get_metric_with_label_values(&[&self.gauges.pubkey_labels.label(leader)])
+                .map(|c| c.set(skip_rate))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the leader of the slot at `slot_index` in the last observed epoch if the leader is
+    /// whitelisted.
+    pub fn current_leader(&self, slot_index: u64) -> Option<&str> {
+        self.whitelisted_leader(slot_index).map(String::as_str)
+    }
+
+    /// Returns the leader of the slot at `slot_in_epoch` in the last observed epoch if the leader is
+    /// whitelisted.
+    fn whitelisted_leader(&self, slot_in_epoch: u64) -> Option<&String> {
+        self.slot_leaders
+            .get(&(slot_in_epoch as usize))
+            .filter(|leader| self.whitelisted_leaders.contains(*leader))
+    }
+
+    /// Returns the slots of whitelisted leaders in the last observed epoch in slot order, paired
+    /// with their leaders.
+    fn whitelisted_slot_leaders(&self) -> impl Iterator<Item = (&usize, &String)> {
+        self.slot_leaders
+            .iter()
+            .filter(move |(_, leader)| self.whitelisted_leaders.contains(*leader))
+    }
+
+    /// Loads the slot leaders in the current epoch `epoch` and determines those that are in
+    /// `node_whitelist`. The leader schedule is only fetched if it is not cached.
+    fn load_slot_leaders(&mut self, epoch: u64, node_whitelist: &Whitelist) -> anyhow::Result<()> {
+        self.slot_leaders = match self.cache.get_slot_leaders(epoch)? {
+            Some(slot_leaders) => {
+                debug!("Restored the leader schedule of epoch {}", epoch);
+                slot_leaders
+            }
+            None => {
+                let slot_leaders = self.get_slot_leaders(None)?;
+                self.cache.set_slot_leaders(epoch, &slot_leaders)?;
+                slot_leaders
+            }
+        };
+        self.whitelisted_leaders = self
+            .slot_leaders
+            .values()
+            .filter(|leader| node_whitelist.contains(leader))
+            .cloned()
+            .collect();
+        Ok(())
+    }
+
+    /// Gets the leader schedule internally and inverts it, returning the slot leaders in `epoch` or
+    /// in the current epoch if `epoch` is `None`.
+    fn get_slot_leaders(&self, epoch: Option<u64>) -> Result<BTreeMap<usize, String>, ClientError> {
+        let mut slot_leaders = BTreeMap::new();
+        let leader_schedule = retry_with_backoff(
+            || {
+                self.gauges.count_rpc_error(
+                    "get_leader_schedule",
+                    self.client.get_leader_schedule(epoch),
+                )
+            },
+            self.max_retries,
+        )?;
+        match leader_schedule {
+            None => (),
+            Some(leader_schedule) => {
+                for (pk, slots) in leader_schedule {
+                    for slot in slots {
+                        slot_leaders.insert(slot, pk.clone());
+                    }
+                }
+            }
+        }
+        Ok(slot_leaders)
+    }
+}
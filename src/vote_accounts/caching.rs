@@ -0,0 +1,123 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{clock::Epoch, pubkey::Pubkey};
+
+/// Name of the tree storing the last seen node pubkey of each vote account.
+pub const VOTE_ACCOUNT_IDENTITIES_TREE_NAME: &str = "vote_account_identities";
+
+/// Name of the tree storing the commission history of each vote account.
+pub const VOTE_ACCOUNT_COMMISSIONS_TREE_NAME: &str = "vote_account_commissions";
+
+/// Name of the tree storing the authority history of each vote account.
+pub const VOTE_ACCOUNT_AUTHORITIES_TREE_NAME: &str = "vote_account_authorities";
+
+/// The commission of a vote account in the last epoch it was seen, and in the epoch before.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct CommissionRecord {
+    /// The last epoch in which the vote account was seen.
+    pub epoch: Epoch,
+    /// The commission last seen in `epoch`.
+    pub commission: u8,
+    /// The commission last seen in an epoch before `epoch`, if the vote account was seen then.
+    pub previous_commission: Option<u8>,
+}
+
+/// The authorized voter and withdrawer of a vote account.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VoteAuthorities {
+    /// The latest authorized voter.
+    pub voter: Pubkey,
+    /// The authorized withdrawer.
+    pub withdrawer: Pubkey,
+}
+
+/// The authorities of a vote account in the last epoch it was seen, and in the epoch before.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct AuthoritiesRecord {
+    /// The last epoch in which the vote account was seen.
+    pub epoch: Epoch,
+    /// The authorities last seen in `epoch`.
+    pub authorities: VoteAuthorities,
+    /// The authorities last seen in an epoch before `epoch`, if the vote account was seen then.
+    pub previous_authorities: Option<VoteAuthorities>,
+}
+
+/// A caching database for the last seen state of vote accounts.
+pub struct VoteAccountsCache {
+    identities_tree: sled::Tree,
+    commissions_tree: sled::Tree,
+    authorities_tree: sled::Tree,
+}
+
+impl VoteAccountsCache {
+    /// Creates a new cache using trees.
+    pub fn new(
+        identities_tree: sled::Tree,
+        commissions_tree: sled::Tree,
+        authorities_tree: sled::Tree,
+    ) -> Self {
+        Self {
+            identities_tree,
+            commissions_tree,
+            authorities_tree,
+        }
+    }
+
+    /// Sets the node pubkey of a vote account. Returns the previously set node pubkey, if it exists.
+    pub fn set_node_pubkey(
+        &self,
+        vote_pubkey: &str,
+        node_pubkey: &str,
+    ) -> anyhow::Result<Option<String>> {
+        self.identities_tree
+            .insert(vote_pubkey, node_pubkey)
+            .context("could not insert node pubkey into database")?
+            .map(|x| String::from_utf8(x.to_vec()))
+            .transpose()
+            .context("previously inserted node pubkey is not valid UTF-8")
+    }
+
+    /// Sets the commission record of a vote account.
+    pub fn set_commission(
+        &self,
+        vote_pubkey: &str,
+        record: &CommissionRecord,
+    ) -> anyhow::Result<()> {
+        self.commissions_tree
+            .insert(vote_pubkey, bincode::serialize(record)?)
+            .context("could not insert commission into database")?;
+        Ok(())
+    }
+
+    /// Returns the commission record of a vote account, if it was stored.
+    pub fn get_commission(&self, vote_pubkey: &str) -> anyhow::Result<Option<CommissionRecord>> {
+        self.commissions_tree
+            .get(vote_pubkey)
+            .context("could not fetch commission from database")?
+            .map(|x| bincode::deserialize(&x))
+            .transpose()
+            .context("could not deserialize fetched commission")
+    }
+
+    /// Sets the authorities record of a vote account.
+    pub fn set_authorities(
+        &self,
+        vote_pubkey: &str,
+        record: &AuthoritiesRecord,
+    ) -> anyhow::Result<()> {
+        self.authorities_tree
+            .insert(vote_pubkey, bincode::serialize(record)?)
+            .context("could not insert authorities into database")?;
+        Ok(())
+    }
+
+    /// Returns the authorities record of a vote account, if it was stored.
+    pub fn get_authorities(&self, vote_pubkey: &str) -> anyhow::Result<Option<AuthoritiesRecord>> {
+        self.authorities_tree
+            .get(vote_pubkey)
+            .context("could not fetch authorities from database")?
+            .map(|x| bincode::deserialize(&x))
+            .transpose()
+            .context("could not deserialize fetched authorities")
+    }
+}
@@ -0,0 +1,194 @@
+//! Changes of vote account state over time.
+
+use crate::config::Whitelist;
+use crate::gauges::PrometheusGauges;
+use crate::vote_accounts::caching::{
+    AuthoritiesRecord, CommissionRecord, VoteAccountsCache, VoteAuthorities,
+};
+use log::warn;
+use solana_client::rpc_client::RpcClient;
+use solana_client::rpc_response::RpcVoteAccountStatus;
+use solana_sdk::{clock::Epoch, pubkey::Pubkey};
+use solana_vote_program::vote_state::VoteState;
+
+pub mod caching;
+
+/// The monitor of changes to vote accounts, with the last seen state kept in the database.
+pub struct VoteAccountsMonitor<'a> {
+    /// Prometheus gauges.
+    gauges: &'a PrometheusGauges,
+    /// Caching database for the last seen vote account state.
+    cache: &'a VoteAccountsCache,
+}
+
+impl<'a> VoteAccountsMonitor<'a> {
+    /// Constructs a new vote accounts monitor.
+    pub fn new(gauges: &'a PrometheusGauges, cache: &'a VoteAccountsCache) -> Self {
+        Self { gauges, cache }
+    }
+
+    /// Counts the changes of node pubkeys of whitelisted vote accounts since they were last seen.
+    pub fn export_identity_changes(
+        &self,
+        vote_accounts: &RpcVoteAccountStatus,
+        vote_accounts_whitelist: &Whitelist,
+    ) -> anyhow::Result<()> {
+        for v in vote_accounts
+            .current
+            .iter()
+            .chain(vote_accounts.delinquent.iter())
+            .filter(|rpc| vote_accounts_whitelist.contains(&rpc.vote_pubkey))
+        {
+            let label = self.gauges.pubkey_labels.label(&v.vote_pubkey);
+            // Make sure the counter is exported even if there were no changes.
+            let counter = self
+                .gauges
+                .vote_account_identity_changed
+                .get_metric_with_label_values(&[&label])?;
+
+            match self.cache.set_node_pubkey(&v.vote_pubkey, &v.node_pubkey)? {
+                Some(previous) if previous != v.node_pubkey => {
+                    warn!(
+                        "Vote account {} changed node pubkey from {} to {}",
+                        v.vote_pubkey, previous, v.node_pubkey
+                    );
+                    counter.inc();
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Exports the change of the commission of whitelisted vote accounts since the previous epoch in
+    /// which they were seen, in percentage points.
+    pub fn export_commission_changes(
+        &self,
+        vote_accounts: &RpcVoteAccountStatus,
+        vote_accounts_whitelist: &Whitelist,
+        epoch: Epoch,
+    ) -> anyhow::Result<()> {
+        for v in vote_accounts
+            .current
+            .iter()
+            .chain(vote_accounts.delinquent.iter())
+            .filter(|rpc| vote_accounts_whitelist.contains(&rpc.vote_pubkey))
+        {
+            let record = self.cache.get_commission(&v.vote_pubkey)?;
+            if let Some(record) = record.filter(|r| r.commission != v.commission) {
+                warn!(
+                    "Vote account {} changed commission from {}% to {}%",
+                    v.vote_pubkey, record.commission, v.commission
+                );
+            }
+            let previous_commission = match record {
+                // The last seen commission becomes the previous one on epoch rollover.
+                Some(record) if record.epoch < epoch => Some(record.commission),
+                Some(record) => record.previous_commission,
+                None => None,
+            };
+            self.cache.set_commission(
+                &v.vote_pubkey,
+                &CommissionRecord {
+                    epoch,
+                    commission: v.commission,
+                    previous_commission,
+                },
+            )?;
+
+            let change = previous_commission
+                .map(|previous| v.commission as i64 - previous as i64)
+                .unwrap_or_default();
+            self.gauges
+                .commission_changed
+                .get_metric_with_label_values(&[&self.gauges.pubkey_labels.label(&v.vote_pubkey)])
+                .map(|m| m.set(change))?;
+        }
+        Ok(())
+    }
+
+    /// Exports whether the authorized voter or withdrawer of whitelisted vote accounts changed since
+    /// the previous epoch in which they were seen. Fetches and deserializes every whitelisted vote
+    /// account.
+    pub fn export_authority_changes(
+        &self,
+        vote_accounts: &RpcVoteAccountStatus,
+        vote_accounts_whitelist: &Whitelist,
+        epoch: Epoch,
+        client: &RpcClient,
+        chunk_size: usize,
+    ) -> anyhow::Result<()> {
+        let vote_pubkeys: Vec<Pubkey> = vote_accounts
+            .current
+            .iter()
+            .chain(vote_accounts.delinquent.iter())
+            .filter(|rpc| vote_accounts_whitelist.contains(&rpc.vote_pubkey))
+            .map(|rpc| rpc.vote_pubkey.parse())
+            .collect::<Result<_, _>>()?;
+
+        for chunk in vote_pubkeys.chunks(chunk_size) {
+            for (vote_pubkey, account) in chunk
+                .iter()
+                .zip(client.get_multiple_accounts(chunk)?)
+                .filter_map(|(pubkey, account)| account.map(|a| (pubkey.to_string(), a)))
+            {
+                let vote_state = match VoteState::deserialize(&account.data) {
+                    Ok(vote_state) => vote_state,
+                    Err(e) => {
+                        warn!(
+                            "Skipping vote account {} that could not be deserialized: {}",
+                            vote_pubkey, e
+                        );
+                        continue;
+                    }
+                };
+                let voter = match vote_state.authorized_voters().last() {
+                    Some((_, voter)) => *voter,
+                    None => continue,
+                };
+                let authorities = VoteAuthorities {
+                    voter,
+                    withdrawer: vote_state.authorized_withdrawer,
+                };
+
+                let record = self.cache.get_authorities(&vote_pubkey)?;
+                if let Some(record) = record.filter(|r| r.authorities != authorities) {
+                    warn!(
+                        "Vote account {} changed authorities from {:?} to {:?}",
+                        vote_pubkey, record.authorities, authorities
+                    );
+                }
+                let previous_authorities = match record {
+                    // The last seen authorities become the previous ones on epoch rollover.
+                    Some(record) if record.epoch < epoch => Some(record.authorities),
+                    Some(record) => record.previous_authorities,
+                    None => None,
+                };
+                self.cache.set_authorities(
+                    &vote_pubkey,
+                    &AuthoritiesRecord {
+                        epoch,
+                        authorities,
+                        previous_authorities,
+                    },
+                )?;
+
+                let label = self.gauges.pubkey_labels.label(&vote_pubkey);
+                let voter_changed =
+                    previous_authorities.map_or(false, |previous| previous.voter != voter);
+                let withdrawer_changed = previous_authorities.map_or(false, |previous| {
+                    previous.withdrawer != authorities.withdrawer
+                });
+                self.gauges
+                    .vote_authority_changed
+                    .get_metric_with_label_values(&[&label, "voter"])
+                    .map(|m| m.set(voter_changed as i64))?;
+                self.gauges
+                    .vote_authority_changed
+                    .get_metric_with_label_values(&[&label, "withdrawer"])
+                    .map(|m| m.set(withdrawer_changed as i64))?;
+            }
+        }
+        Ok(())
+    }
+}
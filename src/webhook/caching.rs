@@ -0,0 +1,73 @@
+use crate::webhook::EpochSummary;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use solana_sdk::clock::Epoch;
+use std::collections::BTreeSet;
+
+/// Name of the tree storing the state of the epoch summary webhook.
+pub const WEBHOOK_TREE_NAME: &str = "webhook";
+
+/// Key under which the summary of the current epoch as of the last update is stored.
+const PENDING_SUMMARY_KEY: &str = "pending_summary";
+
+/// Key under which the last sent summary is stored.
+const LAST_SUMMARY_KEY: &str = "last_summary";
+
+/// The parts of the last sent epoch summary that the next one depends on.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LastSummary {
+    /// The last summarised epoch.
+    pub epoch: Epoch,
+    /// Pubkeys of the validators that were delinquent at the end of `epoch`.
+    pub delinquent: BTreeSet<String>,
+}
+
+/// A caching database for the epoch summaries, so that a restart at the end of an epoch neither
+/// drops nor repeats a summary.
+#[derive(Clone)]
+pub struct WebhookCache {
+    tree: sled::Tree,
+}
+
+impl WebhookCache {
+    /// Creates a new cache using a tree.
+    pub fn new(tree: sled::Tree) -> Self {
+        Self { tree }
+    }
+
+    /// Sets the summary of the current epoch as of the last update.
+    pub fn set_pending_summary(&self, summary: &EpochSummary) -> anyhow::Result<()> {
+        self.tree
+            .insert(PENDING_SUMMARY_KEY, bincode::serialize(summary)?)
+            .context("could not insert pending epoch summary into database")?;
+        Ok(())
+    }
+
+    /// Returns the summary of the current epoch as of the last update, if any was stored.
+    pub fn get_pending_summary(&self) -> anyhow::Result<Option<EpochSummary>> {
+        self.tree
+            .get(PENDING_SUMMARY_KEY)
+            .context("could not fetch pending epoch summary from database")?
+            .map(|x| bincode::deserialize(&x))
+            .transpose()
+            .context("could not deserialize fetched pending epoch summary")
+    }
+
+    /// Sets the last sent summary. Only called once the summary has been posted.
+    pub fn set_last_summary(&self, last_summary: &LastSummary) -> anyhow::Result<()> {
+        self.tree
+            .insert(LAST_SUMMARY_KEY, bincode::serialize(last_summary)?)
+            .context("could not insert last epoch summary into database")?;
+        Ok(())
+    }
+
+    /// Returns the last sent summary, if any was stored.
+    pub fn get_last_summary(&self) -> anyhow::Result<Option<LastSummary>> {
+        self.tree
+            .get(LAST_SUMMARY_KEY)
+            .context("could not fetch last epoch summary from database")?
+            .map(|x| bincode::deserialize(&x))
+            .transpose()
+            .context("could not deserialize fetched last epoch summary")
+    }
+}
@@ -0,0 +1,211 @@
+//! Summaries of finished epochs posted to a webhook.
+
+use crate::gauges::{pubkey_values, PrometheusGauges};
+use crate::webhook::caching::{LastSummary, WebhookCache};
+use futures::FutureExt;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use solana_sdk::clock::Epoch;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+pub mod caching;
+
+/// Default number of retries of a failed webhook request.
+pub const DEFAULT_WEBHOOK_MAX_RETRIES: u32 = 3;
+
+/// Default timeout of a webhook request in seconds.
+pub const DEFAULT_WEBHOOK_TIMEOUT_SECONDS: u64 = 10;
+
+/// Delay before the first retry of a failed webhook request. Doubled on every retry.
+const WEBHOOK_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+fn default_webhook_max_retries() -> u32 {
+    DEFAULT_WEBHOOK_MAX_RETRIES
+}
+
+fn default_webhook_timeout_seconds() -> u64 {
+    DEFAULT_WEBHOOK_TIMEOUT_SECONDS
+}
+
+/// Configuration of the epoch summary webhook.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    /// URL to which summaries are posted.
+    pub url: String,
+    /// Maximum number of retries of a failed request.
+    #[serde(default = "default_webhook_max_retries")]
+    pub max_retries: u32,
+    /// Timeout of a request in seconds.
+    #[serde(default = "default_webhook_timeout_seconds")]
+    pub timeout_seconds: u64,
+}
+
+/// Summary of a finished epoch. Values are keyed by the `pubkey` label of the respective metric.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EpochSummary {
+    /// The finished epoch.
+    pub epoch: Epoch,
+    /// Values of `solana_current_staking_apy`.
+    pub current_staking_apy: BTreeMap<String, f64>,
+    /// Values of `solana_average_staking_apy`.
+    pub average_staking_apy: BTreeMap<String, f64>,
+    /// Values of `solana_validator_rewards`.
+    pub validator_rewards: BTreeMap<String, f64>,
+    /// Values of `solana_skipped_slot_percent`.
+    pub skipped_slot_percent: BTreeMap<String, f64>,
+    /// Pubkeys of delinquent validators.
+    pub delinquent: Vec<String>,
+    /// Pubkeys of validators that became delinquent since the previous summary.
+    pub newly_delinquent: Vec<String>,
+    /// Pubkeys of validators that are no longer delinquent since the previous summary.
+    pub recovered: Vec<String>,
+}
+
+impl EpochSummary {
+    /// Summarises `epoch` from the current values of `gauges`. Has to be called before the
+    /// gauges are updated for the next epoch.
+    pub fn from_gauges(epoch: Epoch, gauges: &PrometheusGauges) -> Self {
+        Self {
+            epoch,
+            current_staking_apy: pubkey_values(&gauges.current_staking_apy),
+            average_staking_apy: pubkey_values(&gauges.average_staking_apy),
+            validator_rewards: pubkey_values(&gauges.validator_rewards),
+            skipped_slot_percent: pubkey_values(&gauges.skipped_slot_percent),
+            delinquent: pubkey_values(&gauges.is_delinquent)
+                .into_iter()
+                .filter(|(_, delinquent)| *delinquent > 0.0)
+                .map(|(pubkey, _)| pubkey)
+                .collect(),
+            newly_delinquent: vec![],
+            recovered: vec![],
+        }
+    }
+
+    /// Compares the delinquent validators with those of the `previous` summary, if any. Without a
+    /// previous summary, every delinquent validator is newly delinquent.
+    fn compare_delinquent(&mut self, previous: Option<&BTreeSet<String>>) {
+        let empty = BTreeSet::new();
+        let previous = previous.unwrap_or(&empty);
+        let current: BTreeSet<String> = self.delinquent.iter().cloned().collect();
+        self.newly_delinquent = current.difference(previous).cloned().collect();
+        self.recovered = previous.difference(&current).cloned().collect();
+    }
+}
+
+/// Posts epoch summaries to a webhook.
+pub struct Webhook {
+    client: reqwest::Client,
+    config: WebhookConfig,
+    /// The task posting a summary, if one was started.
+    in_flight: Option<JoinHandle<()>>,
+}
+
+impl Webhook {
+    /// Makes a new webhook from its configuration.
+    pub fn new(config: WebhookConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_seconds))
+            .build()?;
+        Ok(Self {
+            client,
+            config,
+            in_flight: None,
+        })
+    }
+
+    /// Posts the summary of the last update before `epoch` started in a separate task, unless it has
+    /// already been posted, is still being posted, or there was no update in an earlier epoch. The
+    /// summary is only recorded as sent once it has been posted, so a summary that could not be
+    /// posted is tried again on the next call. Has to be called before the pending summary in
+    /// `cache` is replaced with one of `epoch`.
+    pub fn send_finished_epoch(
+        &mut self,
+        cache: &WebhookCache,
+        epoch: Epoch,
+    ) -> anyhow::Result<()> {
+        if let Some(handle) = &mut self.in_flight {
+            if handle.now_or_never().is_none() {
+                return Ok(());
+            }
+            self.in_flight = None;
+        }
+
+        let mut summary = match cache.get_pending_summary()? {
+            Some(summary) if summary.epoch < epoch => summary,
+            _ => return Ok(()),
+        };
+        let last_summary = cache.get_last_summary()?;
+        if last_summary
+            .as_ref()
+            .map_or(false, |last| last.epoch >= summary.epoch)
+        {
+            return Ok(());
+        }
+        summary.compare_delinquent(last_summary.as_ref().map(|last| &last.delinquent));
+        let sent_summary = LastSummary {
+            epoch: summary.epoch,
+            delinquent: summary.delinquent.iter().cloned().collect(),
+        };
+
+        // The webhook may be slow to respond, which must not hold up the update.
+        let webhook = Self {
+            client: self.client.clone(),
+            config: self.config.clone(),
+            in_flight: None,
+        };
+        let cache = cache.clone();
+        self.in_flight = Some(tokio::spawn(async move {
+            match webhook.send(&summary).await {
+                Ok(()) => {
+                    if let Err(e) = cache.set_last_summary(&sent_summary) {
+                        warn!("Could not record the posted epoch summary: {:?}", e);
+                    }
+                }
+                Err(e) => warn!("Could not post epoch summary to webhook: {:?}", e),
+            }
+        }));
+        Ok(())
+    }
+
+    /// Waits until the summary being posted, if any, has been posted or given up.
+    pub async fn finish(&mut self) {
+        if let Some(handle) = self.in_flight.take() {
+            if let Err(e) = handle.await {
+                warn!("Epoch summary task failed: {:?}", e);
+            }
+        }
+    }
+
+    /// Posts `summary` as JSON, retrying with exponential backoff if the request fails.
+    pub async fn send(&self, summary: &EpochSummary) -> anyhow::Result<()> {
+        let mut delay = WEBHOOK_RETRY_DELAY;
+        let mut retries = 0;
+        loop {
+            let result = self
+                .client
+                .post(&self.config.url)
+                .json(summary)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            match result {
+                Ok(_) => {
+                    debug!("Posted summary of epoch {} to webhook", summary.epoch);
+                    return Ok(());
+                }
+                Err(e) if retries < self.config.max_retries => {
+                    retries += 1;
+                    warn!(
+                        "Webhook request failed, retry {}/{} in {:?}: {}",
+                        retries, self.config.max_retries, delay, e
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}